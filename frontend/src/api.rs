@@ -0,0 +1,229 @@
+//! A small HTTP API for scripts on the same machine.
+//!
+//! - `GET /state` returns `{"runners": [...]}` with the state of every runner, as sent to overlays
+//! - `GET /splits` returns `{"runners": [...]}` with the id, label and splits of every runner
+//! - `POST /start`, `/split`, `/skip`, `/undo`, `/pause`, `/resume` and `/reset` control the first
+//!   runner, or the one with the `id` given as `?runner=<id>`.  Unknown runners are answered
+//!   with a 404.
+//!
+//! The server only listens on loopback addresses.  Requests carrying an `Origin` header are
+//! refused, so web pages open in a browser can not control the timer.
+
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use celeste_autosplit_splits::TimerAction;
+
+use crate::overlay::{read_request, REQUEST_TIMEOUT};
+
+/// Request bodies are never needed, so longer ones are refused instead of read
+const MAX_BODY: usize = 4096;
+
+#[derive(Debug, Default)]
+struct Documents {
+    state: String,
+    splits: String,
+    // The ids of the current runners, in the order they are listed
+    runners: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct ApiServer {
+    documents: Arc<Mutex<Documents>>,
+    // Actions along with the id of the runner they are for
+    commands: Receiver<(usize, TimerAction)>,
+}
+
+impl ApiServer {
+    /// Listens on `addr`, which is `host:port` or just a port to listen on localhost
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let addrs = match addr.parse::<u16>() {
+            Ok(port) => ("127.0.0.1", port).to_socket_addrs()?.collect::<Vec<_>>(),
+            Err(_) => addr.to_socket_addrs()?.collect(),
+        };
+        if let Some(public) = addrs.iter().find(|addr| !addr.ip().is_loopback()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "refusing to listen on {}, which is not a loopback address",
+                    public
+                ),
+            ));
+        }
+        let listener = TcpListener::bind(&addrs[..])?;
+
+        let documents = Arc::new(Mutex::new(Documents {
+            state: "{\"runners\":[]}".to_string(),
+            splits: "{\"runners\":[]}".to_string(),
+            runners: Vec::new(),
+        }));
+        let (sender, commands) = mpsc::channel();
+
+        let shared = Arc::clone(&documents);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("Unable to accept API connection: {}", e);
+                        continue;
+                    }
+                };
+                let (documents, sender) = (Arc::clone(&shared), sender.clone());
+                thread::spawn(move || {
+                    if let Err(e) = serve_connection(stream, &documents, &sender) {
+                        eprintln!("API request failed: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(ApiServer {
+            documents,
+            commands,
+        })
+    }
+
+    /// Replaces what `GET /state` and `GET /splits` return, along with the ids of the runners
+    /// that can be controlled
+    pub fn update(&self, state: String, splits: String, runners: Vec<usize>) {
+        let mut documents = self.documents.lock().expect("Unable to lock API");
+        documents.state = state;
+        documents.splits = splits;
+        documents.runners = runners;
+    }
+
+    /// The commands received since the last call, with the id of the runner they are for
+    pub fn commands(&self) -> impl Iterator<Item = (usize, TimerAction)> + '_ {
+        self.commands.try_iter()
+    }
+}
+
+fn serve_connection(
+    mut stream: TcpStream,
+    documents: &Mutex<Documents>,
+    commands: &Sender<(usize, TimerAction)>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = read_request(&mut stream)?;
+    let mut lines = request.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (method, target) = (
+        request_line.next().unwrap_or_default(),
+        request_line.next().unwrap_or_default(),
+    );
+
+    let mut content_length = 0;
+    for line in lines {
+        if let Some((name, value)) = line.split_once(':') {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("Origin") {
+                return respond(&mut stream, "403 Forbidden", "{\"error\":\"cross origin\"}");
+            }
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return respond(
+            &mut stream,
+            "413 Payload Too Large",
+            "{\"error\":\"body too large\"}",
+        );
+    }
+    // Drain the body so closing the connection does not reset it before the response arrives
+    let mut body = vec![0_u8; content_length];
+    stream.read_exact(&mut body)?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let runner = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("runner="))
+        .map(str::parse::<usize>)
+        .transpose();
+    let runner = match runner {
+        Ok(runner) => runner,
+        Err(_) => return respond(&mut stream, "400 Bad Request", "{\"error\":\"bad runner\"}"),
+    };
+
+    let command = match (method, path) {
+        ("GET", "/state") => {
+            let state = documents.lock().expect("Unable to lock API").state.clone();
+            return respond(&mut stream, "200 OK", &state);
+        }
+        ("GET", "/splits") => {
+            let splits = documents.lock().expect("Unable to lock API").splits.clone();
+            return respond(&mut stream, "200 OK", &splits);
+        }
+        ("POST", "/start") => TimerAction::Start,
+        ("POST", "/split") => TimerAction::Split,
+        ("POST", "/reset") => TimerAction::Reset,
+        ("POST", "/undo") => TimerAction::Undo,
+        ("POST", "/skip") => TimerAction::Skip,
+        ("POST", "/pause") => TimerAction::Pause,
+        ("POST", "/resume") => TimerAction::Resume,
+        (_, "/state")
+        | (_, "/splits")
+        | (_, "/start")
+        | (_, "/split")
+        | (_, "/reset")
+        | (_, "/undo")
+        | (_, "/skip")
+        | (_, "/pause")
+        | (_, "/resume") => {
+            return respond(
+                &mut stream,
+                "405 Method Not Allowed",
+                "{\"error\":\"method not allowed\"}",
+            )
+        }
+        _ => return respond(&mut stream, "404 Not Found", "{\"error\":\"not found\"}"),
+    };
+
+    let runner = {
+        let runners = &documents.lock().expect("Unable to lock API").runners;
+        match runner {
+            Some(id) => runners.iter().copied().find(|&other| other == id),
+            None => runners.first().copied(),
+        }
+    };
+    let runner = match runner {
+        Some(runner) => runner,
+        None => {
+            return respond(
+                &mut stream,
+                "404 Not Found",
+                "{\"error\":\"unknown runner\"}",
+            )
+        }
+    };
+
+    // The timer stopped if nothing receives commands anymore
+    if commands.send((runner, command)).is_err() {
+        return respond(
+            &mut stream,
+            "503 Service Unavailable",
+            "{\"error\":\"timer stopped\"}",
+        );
+    }
+    respond(&mut stream, "202 Accepted", "{}")
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())
+}
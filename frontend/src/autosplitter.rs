@@ -0,0 +1,581 @@
+//! Runs auto splitters compiled to WebAssembly, as written for LiveSplit's auto splitting
+//! runtime with the `asr` crate.
+//!
+//! Modules can only attach to the Celeste process of the runner they belong to, and read its
+//! memory through [`cat::Celeste::read_memory`].  Their timer actions are applied to the splits
+//! like the ones received over the API.  Only the `env` imports of the runtime are provided, so
+//! modules built for WASI or using the settings maps do not load.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use celeste_autosplit_splits::{TimerAction, TimerState};
+use celeste_autosplit_tracer as cat;
+use wasmi::{
+    core::{Trap, F64},
+    Caller, Config, Engine, Linker, Memory, Module, Store, TypedFunc,
+};
+
+/// How often `update` runs until the module sets its own tick rate
+const DEFAULT_TICK_RATE: f64 = 120.0;
+/// The instructions a module may run per call, so a stuck one can not hang the timer
+const FUEL_PER_CALL: u64 = 100_000_000;
+/// The handle of the game, as the only process modules can attach to
+const PROCESS_ID: u64 = 1;
+
+// The flags of `process_get_memory_range_flags`
+const RANGE_READ: u64 = 1 << 1;
+const RANGE_WRITE: u64 = 1 << 2;
+const RANGE_EXECUTE: u64 = 1 << 3;
+const RANGE_PATH: u64 = 1 << 4;
+
+#[derive(Debug)]
+pub enum AutoSplitterError {
+    Io(PathBuf, io::Error),
+    Wasm(wasmi::Error),
+    /// The module does not export this function
+    MissingExport(&'static str),
+}
+
+impl fmt::Display for AutoSplitterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AutoSplitterError::Io(path, e) => {
+                write!(f, "Unable to read {}: {}", path.display(), e)
+            }
+            AutoSplitterError::Wasm(e) => write!(f, "{}", e),
+            AutoSplitterError::MissingExport(name) => {
+                write!(f, "The module does not export `{}`", name)
+            }
+        }
+    }
+}
+
+impl Error for AutoSplitterError {}
+
+impl<E: Into<wasmi::Error>> From<E> for AutoSplitterError {
+    fn from(e: E) -> Self {
+        AutoSplitterError::Wasm(e.into())
+    }
+}
+
+/// The state modules read and change through their imports
+struct Host {
+    celeste: Arc<cat::Celeste>,
+    pid: u32,
+    attached: bool,
+    // The mappings of the game, loaded once per update so the indexes of the memory range
+    // functions agree with each other
+    memory_map: Option<cat::MemoryMap>,
+    settings: HashMap<String, bool>,
+    timer_state: TimerState,
+    // Timer actions taken during the current update
+    commands: Vec<TimerAction>,
+    game_time: Option<u64>,
+    variables: BTreeMap<String, String>,
+    tick_rate: Duration,
+    // Whether the module was told that pausing the game time is not supported
+    warned_game_time_pausing: bool,
+}
+
+impl Host {
+    fn process(&self, process: u64) -> Option<u32> {
+        Some(self.pid).filter(|_| self.attached && process == PROCESS_ID)
+    }
+
+    /// Whether `name` is the name modules know the game by, the file name of its executable
+    fn is_game(&self, name: &str) -> bool {
+        let exe = fs::read_link(format!("/proc/{}/exe", self.pid)).ok();
+        let exe_name = exe.as_ref().and_then(|exe| exe.file_name());
+        // The kernel truncates process names to 15 bytes
+        let comm = fs::read_to_string(format!("/proc/{}/comm", self.pid)).unwrap_or_default();
+        exe_name.is_some_and(|exe_name| exe_name == name)
+            || (!comm.trim_end().is_empty()
+                && name.as_bytes().starts_with(comm.trim_end().as_bytes()))
+    }
+
+    /// The mappings of the module called `name`, which is the file name they are mapped from
+    fn module_range(&self, name: &str) -> Option<(u64, u64)> {
+        let map = self.memory_map.as_ref()?;
+        let mut mappings = map.mappings().iter().filter(|m| {
+            Path::new(&m.path)
+                .file_name()
+                .is_some_and(|file| file == name)
+        });
+        let first = mappings.next()?;
+        let end = mappings.next_back().map_or(first.end, |last| last.end);
+        Some((first.start as u64, (end - first.start) as u64))
+    }
+}
+
+/// An auto splitter module running against one runner's game
+pub struct AutoSplitter {
+    store: Store<Host>,
+    update: TypedFunc<(), ()>,
+    // The fuel handed to the module so far, to top it up to `FUEL_PER_CALL` before each call
+    fuel_added: u64,
+    next_tick: Instant,
+}
+
+impl fmt::Debug for AutoSplitter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AutoSplitter")
+            .field("pid", &self.store.data().pid)
+            .field("attached", &self.store.data().attached)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AutoSplitter {
+    /// Loads the module at `path` for the game `pid`, answering its boolean settings from
+    /// `settings` or their defaults
+    pub fn load(
+        path: &Path,
+        celeste: Arc<cat::Celeste>,
+        pid: u32,
+        settings: HashMap<String, bool>,
+    ) -> Result<Self, AutoSplitterError> {
+        let bytes = fs::read(path).map_err(|e| AutoSplitterError::Io(path.to_path_buf(), e))?;
+
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, &bytes[..])?;
+
+        let host = Host {
+            memory_map: celeste.memory_map(),
+            celeste,
+            pid,
+            attached: false,
+            settings,
+            timer_state: TimerState::NotRunning,
+            commands: Vec::new(),
+            game_time: None,
+            variables: BTreeMap::new(),
+            tick_rate: Duration::from_secs_f64(1.0 / DEFAULT_TICK_RATE),
+            warned_game_time_pausing: false,
+        };
+        let mut store = Store::new(&engine, host);
+        let mut linker = Linker::new(&engine);
+        link_timer(&mut linker)?;
+        link_process(&mut linker)?;
+        link_runtime(&mut linker)?;
+        link_settings(&mut linker)?;
+
+        store.add_fuel(FUEL_PER_CALL)?;
+        let instance = linker.instantiate(&mut store, &module)?.start(&mut store)?;
+        let update = instance
+            .get_typed_func::<(), ()>(&store, "update")
+            .map_err(|_| AutoSplitterError::MissingExport("update"))?;
+
+        Ok(AutoSplitter {
+            store,
+            update,
+            fuel_added: FUEL_PER_CALL,
+            next_tick: Instant::now(),
+        })
+    }
+
+    /// Runs `update` if the module is due for a tick, returning the timer actions it took
+    pub fn update(
+        &mut self,
+        timer_state: TimerState,
+    ) -> Result<Vec<TimerAction>, AutoSplitterError> {
+        let now = Instant::now();
+        if now < self.next_tick {
+            return Ok(Vec::new());
+        }
+        self.next_tick = now + self.store.data().tick_rate;
+
+        let consumed = self.store.fuel_consumed().unwrap_or_default();
+        let remaining = self.fuel_added - consumed;
+        self.store.add_fuel(FUEL_PER_CALL - remaining)?;
+        self.fuel_added += FUEL_PER_CALL - remaining;
+
+        let host = self.store.data_mut();
+        host.timer_state = timer_state;
+        host.memory_map = host.celeste.memory_map();
+        self.update.call(&mut self.store, ())?;
+        Ok(std::mem::take(&mut self.store.data_mut().commands))
+    }
+
+    /// The game time set by the module in milliseconds, if it set one
+    pub fn game_time(&self) -> Option<u64> {
+        self.store.data().game_time
+    }
+
+    /// The variables set by the module, to show along with the timer
+    pub fn variables(&self) -> &BTreeMap<String, String> {
+        &self.store.data().variables
+    }
+}
+
+fn memory(caller: &Caller<'_, Host>) -> Result<Memory, Trap> {
+    caller
+        .get_export("memory")
+        .and_then(|export| export.into_memory())
+        .ok_or_else(|| Trap::new("the module does not export its memory"))
+}
+
+/// Whether `len` bytes at `ptr` fit in the memory of the module, checked before allocating
+/// anything of a length the module asked for
+fn in_memory(caller: &Caller<'_, Host>, ptr: u32, len: u32) -> Result<bool, Trap> {
+    let size = memory(caller)?.data(caller).len() as u64;
+    Ok(ptr as u64 + len as u64 <= size)
+}
+
+fn read_bytes(caller: &Caller<'_, Host>, ptr: u32, len: u32) -> Result<Vec<u8>, Trap> {
+    if !in_memory(caller, ptr, len)? {
+        return Err(Trap::new("a buffer passed to the runtime is out of bounds"));
+    }
+    let mut buf = vec![0; len as usize];
+    memory(caller)?
+        .read(caller, ptr as usize, &mut buf)
+        .map_err(|e| Trap::new(e.to_string()))?;
+    Ok(buf)
+}
+
+fn read_str(caller: &Caller<'_, Host>, ptr: u32, len: u32) -> Result<String, Trap> {
+    String::from_utf8(read_bytes(caller, ptr, len)?)
+        .map_err(|_| Trap::new("a string passed to the runtime is not UTF-8"))
+}
+
+fn write_bytes(caller: &mut Caller<'_, Host>, ptr: u32, bytes: &[u8]) -> Result<(), Trap> {
+    memory(caller)?
+        .write(caller, ptr as usize, bytes)
+        .map_err(|e| Trap::new(e.to_string()))
+}
+
+/// Writes `bytes` to a buffer whose capacity is at `len_ptr`, then stores the length needed
+/// there.  Returns whether the buffer was big enough.
+fn write_sized(
+    caller: &mut Caller<'_, Host>,
+    ptr: u32,
+    len_ptr: u32,
+    bytes: &[u8],
+) -> Result<u32, Trap> {
+    let capacity = u32::from_le_bytes(read_bytes(caller, len_ptr, 4)?.try_into().unwrap());
+    write_bytes(caller, len_ptr, &(bytes.len() as u32).to_le_bytes())?;
+    if bytes.len() > capacity as usize {
+        return Ok(0);
+    }
+    write_bytes(caller, ptr, bytes)?;
+    Ok(1)
+}
+
+fn link_timer(linker: &mut Linker<Host>) -> Result<(), AutoSplitterError> {
+    fn queue(command: TimerAction) -> impl Fn(Caller<'_, Host>) {
+        move |mut caller| caller.data_mut().commands.push(command)
+    }
+
+    linker
+        .func_wrap("env", "timer_get_state", |caller: Caller<'_, Host>| {
+            // The codes of the runtime's `TimerState`
+            match caller.data().timer_state {
+                TimerState::NotRunning => 0u32,
+                TimerState::Running => 1,
+                TimerState::Paused => 2,
+                TimerState::Ended => 3,
+            }
+        })?
+        .func_wrap("env", "timer_start", queue(TimerAction::Start))?
+        .func_wrap("env", "timer_split", queue(TimerAction::Split))?
+        .func_wrap("env", "timer_skip_split", queue(TimerAction::Skip))?
+        .func_wrap("env", "timer_undo_split", queue(TimerAction::Undo))?
+        .func_wrap("env", "timer_reset", queue(TimerAction::Reset))?
+        .func_wrap(
+            "env",
+            "timer_set_variable",
+            |mut caller: Caller<'_, Host>, key_ptr: u32, key_len: u32, ptr: u32, len: u32| {
+                let key = read_str(&caller, key_ptr, key_len)?;
+                let value = read_str(&caller, ptr, len)?;
+                caller.data_mut().variables.insert(key, value);
+                Ok(())
+            },
+        )?
+        .func_wrap(
+            "env",
+            "timer_set_game_time",
+            |mut caller: Caller<'_, Host>, secs: i64, nanos: i32| {
+                let ms = secs.max(0) as u64 * 1000 + nanos.max(0) as u64 / 1_000_000;
+                caller.data_mut().game_time = Some(ms);
+            },
+        )?
+        .func_wrap("env", "timer_pause_game_time", warn_game_time_pausing)?
+        .func_wrap("env", "timer_resume_game_time", warn_game_time_pausing)?;
+    Ok(())
+}
+
+/// Game time only ever changes when the module sets it, so there is nothing to pause
+fn warn_game_time_pausing(mut caller: Caller<'_, Host>) {
+    let host = caller.data_mut();
+    if !host.warned_game_time_pausing {
+        host.warned_game_time_pausing = true;
+        eprintln!(
+            "The auto splitter paused or resumed the game time, which is not supported.  \
+            Only the game time it sets is shown."
+        );
+    }
+}
+
+fn link_process(linker: &mut Linker<Host>) -> Result<(), AutoSplitterError> {
+    linker
+        .func_wrap(
+            "env",
+            "process_attach",
+            |mut caller: Caller<'_, Host>, ptr: u32, len: u32| {
+                if !in_memory(&caller, ptr, len)? {
+                    return Ok(0);
+                }
+                let name = read_str(&caller, ptr, len)?;
+                if !caller.data().is_game(&name) {
+                    return Ok(0);
+                }
+                caller.data_mut().attached = true;
+                Ok(PROCESS_ID)
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_attach_by_pid",
+            |mut caller: Caller<'_, Host>, pid: u64| {
+                if pid != caller.data().pid as u64 {
+                    return 0;
+                }
+                caller.data_mut().attached = true;
+                PROCESS_ID
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_detach",
+            |mut caller: Caller<'_, Host>, process: u64| {
+                if process == PROCESS_ID {
+                    caller.data_mut().attached = false;
+                }
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_is_open",
+            |caller: Caller<'_, Host>, process: u64| {
+                let pid = caller.data().process(process);
+                pid.is_some_and(|pid| Path::new(&format!("/proc/{}", pid)).exists()) as u32
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_read",
+            |mut caller: Caller<'_, Host>, process: u64, addr: u64, ptr: u32, len: u32| {
+                if caller.data().process(process).is_none() || !in_memory(&caller, ptr, len)? {
+                    return Ok(0);
+                }
+                let mut buf = vec![0; len as usize];
+                if caller
+                    .data()
+                    .celeste
+                    .read_memory(addr as usize, &mut buf)
+                    .is_err()
+                {
+                    return Ok(0);
+                }
+                write_bytes(&mut caller, ptr, &buf)?;
+                Ok(1)
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_module_address",
+            |caller: Caller<'_, Host>, process: u64, ptr: u32, len: u32| {
+                if !in_memory(&caller, ptr, len)? {
+                    return Ok(0);
+                }
+                let name = read_str(&caller, ptr, len)?;
+                let host = caller.data();
+                let range = host.process(process).and_then(|_| host.module_range(&name));
+                Ok(range.map_or(0, |(address, _)| address))
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_module_size",
+            |caller: Caller<'_, Host>, process: u64, ptr: u32, len: u32| {
+                if !in_memory(&caller, ptr, len)? {
+                    return Ok(0);
+                }
+                let name = read_str(&caller, ptr, len)?;
+                let host = caller.data();
+                let range = host.process(process).and_then(|_| host.module_range(&name));
+                Ok(range.map_or(0, |(_, size)| size))
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_path",
+            |mut caller: Caller<'_, Host>, process: u64, ptr: u32, len_ptr: u32| {
+                let pid = match caller.data().process(process) {
+                    Some(pid) => pid,
+                    None => return Ok(0),
+                };
+                let path = match fs::read_link(format!("/proc/{}/exe", pid)) {
+                    Ok(path) => path,
+                    Err(_) => return Ok(0),
+                };
+                write_sized(&mut caller, ptr, len_ptr, path.to_string_lossy().as_bytes())
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_list_by_name",
+            |mut caller: Caller<'_, Host>, ptr: u32, len: u32, list_ptr: u32, list_len_ptr: u32| {
+                if !in_memory(&caller, ptr, len)? {
+                    return Ok(0);
+                }
+                let name = read_str(&caller, ptr, len)?;
+                let pids = if caller.data().is_game(&name) {
+                    (caller.data().pid as u64).to_le_bytes().to_vec()
+                } else {
+                    Vec::new()
+                };
+                // The length is in processes rather than bytes
+                let capacity = read_bytes(&caller, list_len_ptr, 4)?;
+                let capacity = u32::from_le_bytes(capacity.try_into().unwrap());
+                write_bytes(
+                    &mut caller,
+                    list_len_ptr,
+                    &(pids.len() as u32 / 8).to_le_bytes(),
+                )?;
+                if pids.len() / 8 > capacity as usize {
+                    return Ok(0);
+                }
+                write_bytes(&mut caller, list_ptr, &pids)?;
+                Ok(1)
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_memory_range_count",
+            |caller: Caller<'_, Host>, process: u64| {
+                let host = caller.data();
+                host.process(process)
+                    .and(host.memory_map.as_ref())
+                    .map_or(0, |map| map.mappings().len() as u64)
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_memory_range_address",
+            |caller: Caller<'_, Host>, process: u64, index: u64| {
+                memory_range(caller.data(), process, index, |m| m.start as u64)
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_memory_range_size",
+            |caller: Caller<'_, Host>, process: u64, index: u64| {
+                memory_range(caller.data(), process, index, |m| (m.end - m.start) as u64)
+            },
+        )?
+        .func_wrap(
+            "env",
+            "process_get_memory_range_flags",
+            |caller: Caller<'_, Host>, process: u64, index: u64| {
+                memory_range(caller.data(), process, index, |m| {
+                    let perms = m.perms.as_bytes();
+                    let mut flags = 0;
+                    for (i, flag) in [RANGE_READ, RANGE_WRITE, RANGE_EXECUTE].iter().enumerate() {
+                        if perms.get(i).is_some_and(|&perm| perm != b'-') {
+                            flags |= flag;
+                        }
+                    }
+                    if !m.path.is_empty() {
+                        flags |= RANGE_PATH;
+                    }
+                    flags
+                })
+            },
+        )?;
+    Ok(())
+}
+
+/// Maps the mapping at `index` of the game with `f`, or returns 0 if there is none
+fn memory_range(host: &Host, process: u64, index: u64, f: impl Fn(&cat::Mapping) -> u64) -> u64 {
+    host.process(process)
+        .and(host.memory_map.as_ref())
+        .and_then(|map| map.mappings().get(index as usize).map(&f))
+        .unwrap_or(0)
+}
+
+fn link_runtime(linker: &mut Linker<Host>) -> Result<(), AutoSplitterError> {
+    linker
+        .func_wrap(
+            "env",
+            "runtime_set_tick_rate",
+            |mut caller: Caller<'_, Host>, ticks_per_second: F64| {
+                let ticks_per_second = ticks_per_second.to_float();
+                if ticks_per_second.is_finite() && ticks_per_second > 0.0 {
+                    caller.data_mut().tick_rate = Duration::from_secs_f64(1.0 / ticks_per_second);
+                }
+            },
+        )?
+        .func_wrap(
+            "env",
+            "runtime_print_message",
+            |caller: Caller<'_, Host>, ptr: u32, len: u32| {
+                eprintln!("Auto splitter: {}", read_str(&caller, ptr, len)?);
+                Ok(())
+            },
+        )?
+        .func_wrap(
+            "env",
+            "runtime_get_os",
+            |mut caller: Caller<'_, Host>, ptr: u32, len_ptr: u32| {
+                write_sized(&mut caller, ptr, len_ptr, std::env::consts::OS.as_bytes())
+            },
+        )?
+        .func_wrap(
+            "env",
+            "runtime_get_arch",
+            |mut caller: Caller<'_, Host>, ptr: u32, len_ptr: u32| {
+                write_sized(&mut caller, ptr, len_ptr, std::env::consts::ARCH.as_bytes())
+            },
+        )?;
+    Ok(())
+}
+
+fn link_settings(linker: &mut Linker<Host>) -> Result<(), AutoSplitterError> {
+    linker
+        .func_wrap(
+            "env",
+            "user_settings_add_bool",
+            |caller: Caller<'_, Host>,
+             key_ptr: u32,
+             key_len: u32,
+             _description_ptr: u32,
+             _description_len: u32,
+             default: u32| {
+                let key = read_str(&caller, key_ptr, key_len)?;
+                let value = caller.data().settings.get(&key).copied();
+                Ok(value.unwrap_or(default != 0) as u32)
+            },
+        )?
+        // Settings are only given on the command line, so there is nothing to show these in
+        .func_wrap(
+            "env",
+            "user_settings_add_title",
+            |_: Caller<'_, Host>, _: u32, _: u32, _: u32, _: u32, _: u32| {},
+        )?
+        .func_wrap(
+            "env",
+            "user_settings_set_tooltip",
+            |_: Caller<'_, Host>, _: u32, _: u32, _: u32, _: u32| {},
+        )?;
+    Ok(())
+}
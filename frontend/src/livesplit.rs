@@ -0,0 +1,172 @@
+use std::{
+    io::{self, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use celeste_autosplit_splits::duration_to_m_s_ms;
+
+/// The port the LiveSplit Server component listens on by default
+pub const DEFAULT_PORT: u16 = 16834;
+
+/// Drives a LiveSplit timer through the LiveSplit Server component's text protocol
+#[derive(Debug)]
+pub struct LiveSplitClient {
+    stream: TcpStream,
+    // The game time sent last, to avoid resending it every poll
+    last_game_time: Option<u64>,
+}
+
+impl LiveSplitClient {
+    /// Connects to `addr`, which is `host:port` or just a host to use the default port
+    pub fn connect(addr: &str) -> io::Result<Self> {
+        let stream = if addr.contains(':') {
+            TcpStream::connect(addr)?
+        } else {
+            TcpStream::connect((addr, DEFAULT_PORT))?
+        };
+        stream.set_nodelay(true)?;
+        Ok(LiveSplitClient {
+            stream,
+            last_game_time: None,
+        })
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        self.stream.write_all(format!("{}\r\n", command).as_bytes())
+    }
+
+    /// Resets any previous attempt and starts a new one, with the game time under our control
+    pub fn begin(&mut self) -> io::Result<()> {
+        self.reset()?;
+        self.start_timer()?;
+        self.send("initgametime")?;
+        // Stop LiveSplit from advancing game time between our updates
+        self.pause_game_time()
+    }
+
+    pub fn start_timer(&mut self) -> io::Result<()> {
+        self.send("starttimer")
+    }
+
+    pub fn split(&mut self) -> io::Result<()> {
+        self.send("split")
+    }
+
+    pub fn unsplit(&mut self) -> io::Result<()> {
+        self.send("unsplit")
+    }
+
+    pub fn skip_split(&mut self) -> io::Result<()> {
+        self.send("skipsplit")
+    }
+
+    pub fn pause(&mut self) -> io::Result<()> {
+        self.send("pause")
+    }
+
+    pub fn resume(&mut self) -> io::Result<()> {
+        self.send("resume")
+    }
+
+    pub fn reset(&mut self) -> io::Result<()> {
+        self.last_game_time = None;
+        self.send("reset")
+    }
+
+    pub fn pause_game_time(&mut self) -> io::Result<()> {
+        self.send("pausegametime")
+    }
+
+    /// Sets the game time in milliseconds, if it changed since the last call
+    pub fn set_game_time(&mut self, time: u64) -> io::Result<()> {
+        if self.last_game_time == Some(time) {
+            return Ok(());
+        }
+        self.last_game_time = Some(time);
+
+        let (m, s, ms) = duration_to_m_s_ms(Duration::from_millis(time));
+        self.send(&format!(
+            "setgametime {}:{:0>2}:{:0>2}.{:0>3}",
+            m / 60,
+            m % 60,
+            s,
+            ms
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, net::TcpListener};
+
+    use super::*;
+
+    /// Runs `commands` against a stand-in LiveSplit server and returns the lines it received
+    fn received<F: FnOnce(&mut LiveSplitClient) -> io::Result<()>>(commands: F) -> Vec<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut client = LiveSplitClient::connect(&addr).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+        commands(&mut client).unwrap();
+        drop(client);
+
+        let mut sent = String::new();
+        server.read_to_string(&mut sent).unwrap();
+        assert!(sent.ends_with("\r\n"), "unterminated command in {:?}", sent);
+        sent.split_terminator("\r\n").map(String::from).collect()
+    }
+
+    #[test]
+    fn begin_takes_control_of_game_time() {
+        assert_eq!(
+            received(|client| client.begin()),
+            ["reset", "starttimer", "initgametime", "pausegametime"]
+        );
+    }
+
+    #[test]
+    fn split_and_reset() {
+        assert_eq!(
+            received(|client| {
+                client.split()?;
+                client.reset()
+            }),
+            ["split", "reset"]
+        );
+    }
+
+    #[test]
+    fn game_time_is_hours_minutes_seconds_millis() {
+        assert_eq!(
+            received(|client| {
+                client.set_game_time(7)?;
+                client.set_game_time(83_456)?;
+                client.set_game_time(3_723_004)
+            }),
+            [
+                "setgametime 0:00:00.007",
+                "setgametime 0:01:23.456",
+                "setgametime 1:02:03.004",
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_game_time_is_sent_once_until_reset() {
+        assert_eq!(
+            received(|client| {
+                client.set_game_time(1_000)?;
+                client.set_game_time(1_000)?;
+                client.reset()?;
+                client.set_game_time(1_000)
+            }),
+            [
+                "setgametime 0:00:01.000",
+                "reset",
+                "setgametime 0:00:01.000"
+            ]
+        );
+    }
+}
@@ -1,36 +1,151 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
+#[cfg(feature = "auto-splitter")]
+use std::collections::HashMap;
 use std::{
-    fs::File,
+    fs::{File, OpenOptions},
     io::{self, BufRead, Write},
+    mem,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use crate::{splits::CurrentSplits, term::ColorName, util::format_time};
+#[cfg(feature = "auto-splitter")]
+use crate::autosplitter::AutoSplitter;
+use crate::{
+    api::ApiServer,
+    livesplit::LiveSplitClient,
+    overlay::{OverlayServer, RunnerState},
+    term::ColorName,
+    textfiles::{TextFiles, TimeFormat},
+};
+use celeste_autosplit_splits::{
+    format_time, format_time_with_units, Attempt, Category, History, ResetRule, Side, Split,
+    SplitEngine, SplitKind, SplitMode, Splits, StartRule, TimerAction, TimerState, Times,
+    TimingMethod,
+};
 use celeste_autosplit_tracer as cat;
 use clap::{crate_version, App, Arg};
-use dialoguer::{Input, Select, Sort};
-use splits::{Split, SplitKind, Splits};
+use dialoguer::{Input, MultiSelect, Select, Sort};
 
-mod splits;
+mod api;
+#[cfg(feature = "auto-splitter")]
+mod autosplitter;
+mod livesplit;
+mod overlay;
 mod term;
-mod util;
+mod textfiles;
 
 fn main() {
-    let arg_matches = App::new("CelesteAutosplitter")
+    let app = App::new("CelesteAutosplitter")
         .version(crate_version!())
         .arg_from_usage("[splits] -s --splits [path] 'the path to the splits file'")
-        // currently broken :(
-        //.arg_from_usage("[celeste] -c --celeste [path] 'the path to the celeste binary to automatically launch and trace without needing root'")
+        .arg_from_usage("[celeste] -c --celeste [path] 'the path to the celeste binary to automatically launch and trace without needing root'")
+        .arg(
+            Arg::with_name("wrapper")
+                .help("a wrapper to launch celeste through, such as the Steam runtime's run.sh")
+                .short("w")
+                .long("wrapper")
+                .takes_value(true)
+                .value_name("path")
+                .requires("celeste"),
+        )
+        .arg(
+            Arg::with_name("launch-timeout")
+                .help("how long to wait for a launched celeste to finish starting, 0 to wait forever")
+                .long("launch-timeout")
+                .takes_value(true)
+                .value_name("seconds")
+                .default_value("120")
+                .validator(|seconds| {
+                    seconds
+                        .parse::<u64>()
+                        .map(|_| ())
+                        .map_err(|_| format!("`{}` is not a number of seconds", seconds))
+                }),
+        )
+        .arg(
+            Arg::with_name("helper")
+                .help("read memory through a running celeste-mem-helper listening on this socket")
+                .long("helper")
+                .takes_value(true)
+                .value_name("socket")
+                .conflicts_with("celeste"),
+        )
+        .arg(
+            Arg::with_name("livesplit")
+                .help("mirror the run to a LiveSplit Server component at host[:port]")
+                .long("livesplit")
+                .takes_value(true)
+                .value_name("address")
+                .conflicts_with("edit-splits"),
+        )
+        .arg(
+            Arg::with_name("overlay")
+                .help("serve the timer to browser source overlays on [host:]port")
+                .long("overlay")
+                .takes_value(true)
+                .value_name("address")
+                .conflicts_with("edit-splits"),
+        )
+        .arg(
+            Arg::with_name("api")
+                .help("serve the timer state and accept split commands over HTTP on [host:]port")
+                .long("api")
+                .takes_value(true)
+                .value_name("address")
+                .conflicts_with("edit-splits"),
+        )
+        .arg(
+            Arg::with_name("text-files")
+                .help("keep text files with the timer and split info in this directory for OBS")
+                .long("text-files")
+                .takes_value(true)
+                .value_name("dir")
+                .conflicts_with("edit-splits"),
+        )
+        .arg(
+            Arg::with_name("text-format")
+                .help("how times are written to the text files")
+                .long("text-format")
+                .takes_value(true)
+                .value_name("format")
+                .possible_values(TimeFormat::NAMES)
+                .default_value("plain")
+                .requires("text-files"),
+        )
         .arg(
             Arg::with_name("edit-splits")
                 .help("iteractive editor for the splits file")
                 .short("e")
                 .long("edit-splits")
                 .conflicts_with("celeste"),
+        );
+    #[cfg(feature = "auto-splitter")]
+    let app = app
+        .arg(
+            Arg::with_name("auto-splitter")
+                .help("let a WebAssembly auto splitter written for LiveSplit drive the splits")
+                .long("auto-splitter")
+                .takes_value(true)
+                .value_name("path")
+                .conflicts_with("edit-splits"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("auto-splitter-setting")
+                .help("set a boolean setting of the auto splitter")
+                .long("auto-splitter-setting")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("key=true|false")
+                .validator(|setting| parse_setting(&setting).map(|_| ()))
+                .requires("auto-splitter"),
+        );
+    let arg_matches = app.get_matches();
 
     let stdin = io::stdin();
     let stdout = io::stdout();
@@ -49,10 +164,67 @@ fn main() {
     if arg_matches.is_present("edit-splits") {
         splits_menu(&path);
     } else {
-        display_timer(&path);
+        let attach = if let Some(celeste) = arg_matches.value_of("celeste") {
+            Attach::Launch(cat::LaunchOptions {
+                celeste: PathBuf::from(celeste),
+                wrapper: arg_matches.value_of("wrapper").map(PathBuf::from),
+                timeout: arg_matches
+                    .value_of("launch-timeout")
+                    .and_then(|seconds| seconds.parse().ok())
+                    .filter(|&seconds| seconds > 0)
+                    .map(Duration::from_secs),
+                ..Default::default()
+            })
+        } else if let Some(socket) = arg_matches.value_of("helper") {
+            Attach::Helper(PathBuf::from(socket))
+        } else {
+            Attach::Select
+        };
+        let options = TimerOptions {
+            livesplit: arg_matches.value_of("livesplit"),
+            overlay: arg_matches.value_of("overlay"),
+            api: arg_matches.value_of("api"),
+            text_files: arg_matches.value_of("text-files").map(|dir| {
+                let format = arg_matches.value_of("text-format").unwrap_or("plain");
+                (PathBuf::from(dir), TimeFormat::from_name(format).unwrap())
+            }),
+            #[cfg(feature = "auto-splitter")]
+            auto_splitter: arg_matches.value_of("auto-splitter").map(|path| {
+                let settings = arg_matches
+                    .values_of("auto-splitter-setting")
+                    .into_iter()
+                    .flatten()
+                    .map(|setting| parse_setting(setting).unwrap())
+                    .collect();
+                (PathBuf::from(path), settings)
+            }),
+        };
+        display_timer(&path, attach, options);
     }
 }
 
+/// Parses an auto splitter setting given as `key=true` or `key=false`
+#[cfg(feature = "auto-splitter")]
+fn parse_setting(setting: &str) -> Result<(String, bool), String> {
+    let (key, value) = setting
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` is not key=value", setting))?;
+    let value = value
+        .parse()
+        .map_err(|_| format!("`{}` is not true or false", value))?;
+    Ok((key.to_string(), value))
+}
+
+/// How to get access to the memory of the game
+enum Attach {
+    /// Pick from the running Celeste processes
+    Select,
+    /// Start Celeste as a child process
+    Launch(cat::LaunchOptions),
+    /// Connect to a privileged helper listening on a socket
+    Helper(PathBuf),
+}
+
 fn write_splits(splits: &Splits, splits_path: &str) {
     let splits_str = toml::to_string_pretty(&splits).expect("Failed to serialize");
     // TODO: keep backup first?
@@ -62,6 +234,19 @@ fn write_splits(splits: &Splits, splits_path: &str) {
         .expect("Failed to write to file");
 }
 
+/// Where the attempts at the splits in `splits_path` are kept, next to them
+fn history_path(splits_path: &str) -> PathBuf {
+    Path::new(splits_path).with_extension("history.toml")
+}
+
+/// Adds `attempts` to the end of the history file, creating it if needed
+fn append_history(path: &Path, attempts: Vec<Attempt>) -> io::Result<()> {
+    // Tables of the same array can be appended one after the other
+    let history = toml::to_string(&History { attempts }).map_err(io::Error::other)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(history.as_bytes())
+}
+
 fn splits_menu(splits_path: &str) {
     let mut splits: Splits = toml::from_str(
         &std::fs::read_to_string(splits_path)
@@ -80,9 +265,29 @@ fn splits_menu(splits_path: &str) {
         match selection {
             Some(0) => {
                 term::writeln("Current Splits", ColorName::Cyan, None);
+                let reset_rules = splits.reset_rules();
+                let reset_rules = if reset_rules.is_empty() {
+                    "never".to_string()
+                } else {
+                    reset_rules
+                        .iter()
+                        .map(|rule| rule.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+                println!(
+                    "{}, timed by {}, starting on {}, resetting on {}\n",
+                    splits.split_mode,
+                    splits.timing_method(),
+                    splits.start_rule(),
+                    reset_rules
+                );
                 for split in splits.splits.iter() {
                     println!("{}\n", split.display_long());
                 }
+                if let Err(e) = splits.validate() {
+                    term::writeln(format!("Invalid splits: {}", e), ColorName::BrightRed, None);
+                }
             }
             Some(1) => edit_menu(&mut splits, splits_path),
             None | Some(2) => {
@@ -99,7 +304,16 @@ fn edit_menu(splits: &mut Splits, splits_path: &str) {
     loop {
         let choice = Select::new()
             .with_prompt("What to edit (press `q` to cancel)")
-            .items(&["Add", "Delete", "Edit", "Move"])
+            .items(&[
+                "Add",
+                "Delete",
+                "Edit",
+                "Move",
+                "Split mode",
+                "Timing method",
+                "Start rule",
+                "Reset rules",
+            ])
             .default(0)
             .interact_opt()
             .expect("Unable to display options");
@@ -151,6 +365,7 @@ fn edit_menu(splits: &mut Splits, splits_path: &str) {
                     name,
                     chapter,
                     split_kind: kind,
+                    pb_time: None,
                 };
 
                 // TODO: sort splits
@@ -296,6 +511,107 @@ fn edit_menu(splits: &mut Splits, splits_path: &str) {
 
                 write_splits(splits, splits_path);
             }
+            Some(4) => {
+                splits.split_mode = select_split_mode();
+                write_splits(splits, splits_path);
+            }
+            Some(5) => {
+                let default = format!(
+                    "Default of the split mode ({})",
+                    splits.split_mode.default_timing_method()
+                );
+                let method_idx = Select::new()
+                    .with_prompt("Which time counts for the run?")
+                    .default(0)
+                    .items(&[default.as_str(), "Chapter time", "File time", "Real time"])
+                    .interact()
+                    .expect("Unable to display prompt");
+                splits.timing_method = match method_idx {
+                    0 => None,
+                    1 => Some(TimingMethod::ChapterTime),
+                    2 => Some(TimingMethod::FileTime),
+                    3 => Some(TimingMethod::RealTime),
+                    _ => {
+                        unreachable!("encountered an invalid selection")
+                    }
+                };
+                write_splits(splits, splits_path);
+            }
+            Some(6) => {
+                let default = format!(
+                    "Default of the split mode ({})",
+                    StartRule::default_for(splits.split_mode)
+                );
+                let rule_idx = Select::new()
+                    .with_prompt("When does the run start?")
+                    .default(0)
+                    .items(&[
+                        default.as_str(),
+                        "Chapter start",
+                        "File select",
+                        "Leaving a room",
+                        "Manual",
+                        "On launch",
+                    ])
+                    .interact()
+                    .expect("Unable to display prompt");
+                splits.start_rule = match rule_idx {
+                    0 => None,
+                    1 => Some(StartRule::ChapterStart),
+                    2 => Some(StartRule::FileSelect),
+                    3 => {
+                        let room: String = Input::new()
+                            .with_prompt("Room name")
+                            .interact_text()
+                            .expect("Unable to display prompt");
+                        Some(StartRule::LeaveRoom { room })
+                    }
+                    4 => Some(StartRule::Manual),
+                    5 => Some(StartRule::OnLaunch),
+                    _ => {
+                        unreachable!("encountered an invalid selection")
+                    }
+                };
+                write_splits(splits, splits_path);
+            }
+            Some(7) => {
+                let current = splits.reset_rules();
+                let enter_room = current
+                    .iter()
+                    .any(|rule| matches!(rule, ResetRule::EnterRoom { .. }));
+                let fixed = [
+                    ResetRule::ChapterTimeBackwards,
+                    ResetRule::ChapterRestart,
+                    ResetRule::ReturnToMap,
+                    ResetRule::SaveAndQuit,
+                ];
+                let mut items = fixed
+                    .iter()
+                    .map(|rule| (rule.to_string(), current.contains(rule)))
+                    .collect::<Vec<_>>();
+                items.push(("entering a room again".to_string(), enter_room));
+                let selected = MultiSelect::new()
+                    .with_prompt("When is the run reset? (use spacebar to select)")
+                    .items_checked(&items)
+                    .interact()
+                    .expect("Unable to display prompt");
+
+                let mut rules = Vec::new();
+                for idx in selected {
+                    match fixed.get(idx) {
+                        Some(rule) => rules.push(rule.clone()),
+                        None => {
+                            let room: String = Input::new()
+                                .with_prompt("Room name")
+                                .interact_text()
+                                .expect("Unable to display prompt");
+                            rules.push(ResetRule::EnterRoom { room });
+                        }
+                    }
+                }
+                splits.reset_rules = Some(rules);
+                write_splits(splits, splits_path);
+            }
             None => break,
             _ => {
                 unreachable!("encountered an invalid selection")
@@ -304,45 +620,332 @@ fn edit_menu(splits: &mut Splits, splits_path: &str) {
     }
 }
 
-fn display_timer(splits_path: &str) {
-    let stdin = io::stdin();
-    let stdout = io::stdout();
+fn select_split_mode() -> SplitMode {
+    let mode_idx = Select::new()
+        .with_prompt("What sort of run are these splits for?")
+        .default(0)
+        .items(&["Individual level", "Full game", "Custom"])
+        .interact()
+        .expect("Unable to display prompt");
+    match mode_idx {
+        0 => {
+            let chapter: i32 = Input::new()
+                .with_prompt("What chapter is the run?")
+                .interact_text()
+                .expect("Unable to display prompt");
+            let side_idx = Select::new()
+                .with_prompt("Which side?")
+                .default(0)
+                .items(Side::ALL)
+                .interact()
+                .expect("Unable to display prompt");
+            SplitMode::IndividualLevel {
+                chapter,
+                side: Side::ALL[side_idx],
+            }
+        }
+        1 => {
+            let category_idx = Select::new()
+                .with_prompt("Which category?")
+                .default(0)
+                .items(Category::ALL)
+                .interact()
+                .expect("Unable to display prompt");
+            SplitMode::FullGame {
+                category: Category::ALL[category_idx],
+            }
+        }
+        2 => SplitMode::Custom,
+        _ => {
+            unreachable!("encountered an invalid selection")
+        }
+    }
+}
+
+fn select_celeste() -> Vec<u32> {
+    let candidates = match cat::find_celeste() {
+        Ok(candidates) => candidates,
+        Err(cat::PIDError::NotFound) => {
+            term::writeln("Waiting for Celeste to start...", ColorName::Yellow, None);
+            cat::wait_for_celeste(None).expect("Unable to search for Celeste")
+        }
+        Err(e) => panic!("Unable to search for Celeste: {:?}", e),
+    };
+
+    if let [only] = candidates.as_slice() {
+        return vec![only.pid];
+    }
+
+    let selected = MultiSelect::new()
+        .with_prompt("Found multiple Celeste processes, use spacebar to select the ones to trace")
+        .items_checked(
+            &candidates
+                .iter()
+                .enumerate()
+                .map(|(idx, candidate)| (display_candidate(candidate), idx == 0))
+                .collect::<Vec<(String, bool)>>(),
+        )
+        .interact()
+        .expect("Unable to display prompt");
+    if selected.is_empty() {
+        eprintln!("No Celeste process selected");
+        process::exit(1);
+    }
+    selected
+        .into_iter()
+        .map(|idx| candidates[idx].pid)
+        .collect()
+}
+
+fn display_candidate(candidate: &cat::CelesteProcess) -> String {
+    let exe = candidate.exe.as_ref().map_or_else(
+        || "<unknown exe>".to_string(),
+        |exe| exe.display().to_string(),
+    );
+    let uptime = candidate
+        .start_time
+        .and_then(|start| start.elapsed().ok())
+        .map_or_else(
+            || "unknown uptime".to_string(),
+            |uptime| format!("up {}", format_time_with_units(uptime)),
+        );
+    format!("PID {} - {} ({})", candidate.pid, exe, uptime)
+}
+
+/// A traced game along with the progress through its splits
+struct Runner {
+    // Addresses the runner over the API, never reused even when other runners are gone
+    id: usize,
+    label: String,
+    #[cfg(feature = "auto-splitter")]
+    pid: u32,
+    celeste: Arc<cat::Celeste>,
+    engine: SplitEngine,
+    livesplit: Option<LiveSplitClient>,
+    // Drives the splits instead of the splits file when there is one
+    #[cfg(feature = "auto-splitter")]
+    auto_splitter: Option<AutoSplitter>,
+}
+
+impl Runner {
+    fn new(id: usize, pid: u32, celeste: cat::Celeste, splits: &Splits) -> Self {
+        Runner {
+            id,
+            label: format!("PID {}", pid),
+            #[cfg(feature = "auto-splitter")]
+            pid,
+            celeste: Arc::new(celeste),
+            engine: SplitEngine::new(splits),
+            livesplit: None,
+            #[cfg(feature = "auto-splitter")]
+            auto_splitter: None,
+        }
+    }
+
+    /// The times of the run at `dump`, read at `now`, with the game time of the auto splitter if
+    /// it keeps one
+    fn times(&self, dump: &cat::Dump, now: Instant) -> Times {
+        let times = self.engine.times(dump, now);
+        #[cfg(feature = "auto-splitter")]
+        if let Some(game) = self.auto_splitter.as_ref().and_then(|a| a.game_time()) {
+            return Times { game, ..times };
+        }
+        times
+    }
+
+    /// Moves the run along with `dump`, through the auto splitter if there is one
+    fn update(&mut self, dump: &cat::Dump, now: Instant) {
+        self.update_game_time(dump, now);
+        if self.run_auto_splitter(dump, now) {
+            return;
+        }
+        for action in self.engine.update(dump, now) {
+            self.mirror(action);
+        }
+    }
+
+    /// Applies an action asked for from outside, mirroring it in LiveSplit if it changed anything
+    fn apply(&mut self, action: TimerAction, dump: &cat::Dump, now: Instant) {
+        if self.engine.apply(action, dump, now) {
+            self.mirror(action);
+        }
+    }
+
+    /// Sends the game time to LiveSplit, disconnecting from it on failure
+    fn update_game_time(&mut self, dump: &cat::Dump, now: Instant) {
+        let game_time = self.times(dump, now).game;
+        if let Some(livesplit) = &mut self.livesplit {
+            if let Err(e) = livesplit.set_game_time(game_time) {
+                eprintln!("Lost connection to LiveSplit: {}", e);
+                self.livesplit = None;
+            }
+        }
+    }
+
+    /// Takes `action` in LiveSplit as well, disconnecting from it on failure
+    fn mirror(&mut self, action: TimerAction) {
+        let livesplit = match &mut self.livesplit {
+            Some(livesplit) => livesplit,
+            None => return,
+        };
+
+        let result = match action {
+            TimerAction::Start => livesplit.begin(),
+            TimerAction::Split => livesplit.split(),
+            TimerAction::Skip => livesplit.skip_split(),
+            TimerAction::Undo => livesplit.unsplit(),
+            TimerAction::Pause => livesplit.pause(),
+            TimerAction::Resume => livesplit.resume(),
+            TimerAction::Reset => livesplit.reset(),
+        };
+        if let Err(e) = result {
+            eprintln!("Lost connection to LiveSplit: {}", e);
+            self.livesplit = None;
+        }
+    }
+
+    /// Lets the auto splitter update the splits, returning false if there is none
+    #[cfg(feature = "auto-splitter")]
+    fn run_auto_splitter(&mut self, dump: &cat::Dump, now: Instant) -> bool {
+        if let Some(auto_splitter) = &mut self.auto_splitter {
+            match auto_splitter.update(self.engine.timer().state()) {
+                Ok(actions) => {
+                    for action in actions {
+                        self.apply(action, dump, now);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Stopped the auto splitter of {}: {}", self.label, e);
+                    self.auto_splitter = None;
+                }
+            }
+            return true;
+        }
+        false
+    }
+
+    #[cfg(not(feature = "auto-splitter"))]
+    fn run_auto_splitter(&mut self, _dump: &cat::Dump, _now: Instant) -> bool {
+        false
+    }
+}
+
+fn connect(pid: u32) -> cat::Celeste {
+    cat::Celeste::new(pid).unwrap_or_else(|e| {
+        eprintln!("Unable to connect to Celeste: {}", e);
+        process::exit(1);
+    })
+}
 
+/// Everything the timer can be hooked up to besides the terminal
+struct TimerOptions<'a> {
+    // Address of a LiveSplit Server to mirror the run to
+    livesplit: Option<&'a str>,
+    // Addresses to serve overlays and the HTTP API on
+    overlay: Option<&'a str>,
+    api: Option<&'a str>,
+    // Directory to keep text files in, and how to write times in them
+    text_files: Option<(PathBuf, TimeFormat)>,
+    // A WebAssembly auto splitter and its settings
+    #[cfg(feature = "auto-splitter")]
+    auto_splitter: Option<(PathBuf, HashMap<String, bool>)>,
+}
+
+fn display_timer(splits_path: &str, attach: Attach, options: TimerOptions) {
     let splits: Splits = toml::from_str(
         &std::fs::read_to_string(splits_path)
             .unwrap_or_else(|_| panic!("Unable to read splits file at `{}`", splits_path)),
     )
     .unwrap_or_else(|_| panic!("Unable to parse splits file `{}`", splits_path));
+    if let Err(e) = splits.validate() {
+        eprintln!("Invalid splits file `{}`: {}", splits_path, e);
+        process::exit(1);
+    }
+    let history = history_path(splits_path);
 
-    let mut splits = CurrentSplits {
-        completed_splits: vec![],
-        todo_splits: splits.splits,
+    // Keep the launched process around for as long as the timer runs
+    let (mut runners, _child) = match attach {
+        Attach::Launch(options) => {
+            term::writeln("Launching Celeste...", ColorName::Yellow, None);
+            let launched = cat::launch_celeste(&options).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            });
+            let runner = Runner::new(0, launched.pid, launched.celeste, &splits);
+            (vec![runner], Some(launched.child))
+        }
+        Attach::Select => {
+            let runners = select_celeste()
+                .into_iter()
+                .enumerate()
+                .map(|(id, pid)| Runner::new(id, pid, connect(pid), &splits))
+                .collect();
+            (runners, None)
+        }
+        Attach::Helper(socket) => {
+            let client = cat::HelperClient::connect(&socket).unwrap_or_else(|e| {
+                eprintln!("Unable to connect to helper at {}: {}", socket.display(), e);
+                process::exit(1);
+            });
+            let pid = client.pid();
+            let celeste = cat::Celeste::with_source(Box::new(client)).unwrap_or_else(|e| {
+                eprintln!("Unable to connect to Celeste: {}", e);
+                process::exit(1);
+            });
+            (vec![Runner::new(0, pid, celeste, &splits)], None)
+        }
     };
+    for runner in &runners {
+        eprintln!("Connected to {}", runner.celeste.domain_name());
+    }
 
-    let celeste_pid = cat::find_celeste();
+    if let Some(addr) = options.livesplit {
+        let client = LiveSplitClient::connect(addr).unwrap_or_else(|e| {
+            eprintln!("Unable to connect to LiveSplit at {}: {}", addr, e);
+            process::exit(1);
+        });
+        if runners.len() > 1 {
+            println!("LiveSplit will follow {}", runners[0].label);
+        }
+        runners[0].livesplit = Some(client);
+    }
 
-    let pid = celeste_pid.unwrap_or_else(|_| {
-        stdout
-            .lock()
-            .write_all(b"Unable to find Celeste, please enter its PID: ")
-            .unwrap();
-        stdout.lock().flush().unwrap();
+    #[cfg(feature = "auto-splitter")]
+    if let Some((path, settings)) = &options.auto_splitter {
+        for runner in runners.iter_mut() {
+            let celeste = Arc::clone(&runner.celeste);
+            let auto_splitter = AutoSplitter::load(path, celeste, runner.pid, settings.clone())
+                .unwrap_or_else(|e| {
+                    eprintln!("Unable to load auto splitter {}: {}", path.display(), e);
+                    process::exit(1);
+                });
+            runner.auto_splitter = Some(auto_splitter);
+        }
+    }
 
-        loop {
-            let mut line = String::new();
-            stdin.lock().read_line(&mut line).unwrap();
+    let overlay = options.overlay.map(|addr| {
+        OverlayServer::bind(addr).unwrap_or_else(|e| {
+            eprintln!("Unable to start the overlay server on {}: {}", addr, e);
+            process::exit(1);
+        })
+    });
 
-            match line.trim_end().parse::<u32>() {
-                Ok(pid) => return pid,
-                Err(_) => {
-                    stdout.lock().write_all(b"Please enter a number: ").unwrap();
-                    stdout.lock().flush().unwrap();
-                }
-            }
-        }
+    let api = options.api.map(|addr| {
+        ApiServer::bind(addr).unwrap_or_else(|e| {
+            eprintln!("Unable to start the API server on {}: {}", addr, e);
+            process::exit(1);
+        })
     });
 
-    let celeste = cat::Celeste::new(pid);
+    let mut text_files = options.text_files.map(|(dir, format)| {
+        TextFiles::create(&dir, format).unwrap_or_else(|e| {
+            eprintln!("Unable to create text files in {}: {}", dir.display(), e);
+            process::exit(1);
+        })
+    });
+    if text_files.is_some() && runners.len() > 1 {
+        println!("Text files will follow {}", runners[0].label);
+    }
 
     term::clear();
     term::writeln(
@@ -351,82 +954,189 @@ fn display_timer(splits_path: &str) {
         None,
     );
 
-    thread::sleep(Duration::from_secs(5));
+    while !runners.is_empty() {
+        let show_label = runners.len() > 1;
+        let mut runner_states = Vec::new();
+        let mut runner_splits = Vec::new();
+        let commands = api
+            .as_ref()
+            .map_or_else(Vec::new, |api| api.commands().collect::<Vec<_>>());
+        let mut first = true;
+        runners.retain_mut(|runner| {
+            let (id, is_first) = (runner.id, mem::replace(&mut first, false));
+            let dump = match runner.celeste.get_data() {
+                Ok(dump) => dump,
+                // The game was mid-update every time, try again next poll
+                Err(cat::TraceError::Inconsistent(_)) => return true,
+                Err(e) => {
+                    eprintln!("Lost connection to Celeste ({}): {}", runner.label, e);
+                    return false;
+                }
+            };
+            let now = Instant::now();
 
-    loop {
-        let dump = celeste.get_data();
-
-        while let Some(split) = splits.todo_splits.first() {
-            match split.is_accomplished(&dump) {
-                true => {
-                    let removed = splits.todo_splits.remove(0);
-                    splits
-                        .completed_splits
-                        .push((removed, dump.autosplitter_info.chapter_time()));
+            runner.update(&dump, now);
+            for &(_, action) in commands.iter().filter(|&&(other, _)| other == id) {
+                runner.apply(action, &dump, now);
+            }
+            let attempts = runner.engine.take_attempts();
+            if !attempts.is_empty() {
+                if let Err(e) = append_history(&history, attempts) {
+                    eprintln!("Unable to save attempt to {}: {}", history.display(), e);
+                }
+            }
+            if show_label {
+                term::writeln(
+                    format!("\n======== {} ========", runner.label),
+                    ColorName::Cyan,
+                    None,
+                );
+            }
+            let times = runner.times(&dump, now);
+            display_dump(&runner.engine, times, &dump);
+            #[cfg(feature = "auto-splitter")]
+            if let Some(auto_splitter) = &runner.auto_splitter {
+                for (key, value) in auto_splitter.variables() {
+                    term::writeln(format!("{}: {}", key, value), None, None);
+                }
+            }
+            let state = RunnerState {
+                id: runner.id,
+                label: &runner.label,
+                splits: runner.engine.timer().splits(),
+                dump: &dump,
+                state: runner.engine.timer().state(),
+                method: runner.engine.timing_method(),
+                times,
+            };
+            if let (Some(files), true) = (&mut text_files, is_first) {
+                let chapter_timed = runner.engine.game_clock() == TimingMethod::ChapterTime;
+                if let Err(e) = files.update(&state, chapter_timed) {
+                    eprintln!("Unable to write text files: {}", e);
+                    text_files = None;
                 }
-                false => break,
             }
+            if overlay.is_some() || api.is_some() {
+                runner_states.push(state.to_json());
+                runner_splits.push(format!(
+                    "{{\"id\":{},\"label\":{},\"splits\":{}}}",
+                    runner.id,
+                    cat::json_string(&runner.label),
+                    state.splits_json()
+                ));
+            }
+            true
+        });
+        let states = format!("{{\"runners\":[{}]}}", runner_states.join(","));
+        if let Some(api) = &api {
+            let splits = format!("{{\"runners\":[{}]}}", runner_splits.join(","));
+            let ids = runners.iter().map(|runner| runner.id).collect();
+            api.update(states.clone(), splits, ids);
+        }
+        if let Some(overlay) = &overlay {
+            overlay.broadcast(states);
         }
 
-        //term::clear();
+        thread::sleep(Duration::from_millis(12));
+    }
 
-        if dump.autosplitter_info.chapter == -1 {
-            term::writeln("No Chapter", ColorName::Yellow, None);
-        } else {
-            term::writeln(
-                format!(
-                    "Chapter {} room {}",
-                    dump.autosplitter_info.chapter,
-                    dump.level_name()
-                ),
-                ColorName::Yellow,
-                None,
-            );
-        }
+    process::exit(1);
+}
+
+fn display_dump(engine: &SplitEngine, times: Times, dump: &cat::Dump) {
+    //term::clear();
+    let timer = engine.timer();
+    let method = engine.timing_method();
+    let splits = timer.splits();
 
+    term::writeln(
+        format!(
+            "Timer: {} ({}, {})",
+            format_time(Duration::from_millis(times.get(method))),
+            method,
+            timer.state()
+        ),
+        ColorName::BrightCyan,
+        None,
+    );
+    term::writeln(
+        format!(
+            "Game time: {}  Real time: {}",
+            format_time(Duration::from_millis(times.game)),
+            format_time(Duration::from_millis(times.real))
+        ),
+        ColorName::Gray,
+        None,
+    );
+    if timer.state() == TimerState::NotRunning {
         term::writeln(
-            format!(
-                "Chapter time: {}",
-                format_time(Duration::from_millis(dump.autosplitter_info.chapter_time()))
-            ),
-            ColorName::Green,
+            format!("Waiting for {}", engine.start_rule()),
+            ColorName::Gray,
             None,
         );
+    }
 
+    if dump.autosplitter_info.chapter == -1 {
+        term::writeln("No Chapter", ColorName::Yellow, None);
+    } else {
         term::writeln(
             format!(
-                "File time: {}",
-                format_time(Duration::from_millis(dump.autosplitter_info.file_time()))
+                "Chapter {} room {}",
+                dump.autosplitter_info.chapter,
+                dump.level_name()
             ),
-            ColorName::BrightMagenta,
-            None,
-        );
-        term::writeln(
-            format!("Deaths: {}", dump.death_count),
-            ColorName::Red,
+            ColorName::Yellow,
             None,
         );
+    }
 
-        term::writeln(
-            "\n################\nCompleted Splits\n################\n",
-            ColorName::White,
-            ColorName::Gray,
-        );
+    term::writeln(
+        format!(
+            "Chapter time: {}",
+            format_time(Duration::from_millis(dump.autosplitter_info.chapter_time()))
+        ),
+        ColorName::Green,
+        None,
+    );
 
-        for split in splits.completed_splits.iter() {
-            term::writeln(split.0.display_complete(split.1), ColorName::White, None);
-        }
+    term::writeln(
+        format!(
+            "File time: {}",
+            format_time(Duration::from_millis(dump.autosplitter_info.file_time()))
+        ),
+        ColorName::BrightMagenta,
+        None,
+    );
+    term::writeln(
+        format!("Deaths: {}", dump.death_count),
+        ColorName::Red,
+        None,
+    );
 
-        term::writeln(
-            "\n###########\nTODO Splits\n###########\n",
-            ColorName::White,
-            ColorName::Gray,
-        );
+    term::writeln(
+        "\n################\nCompleted Splits\n################\n",
+        ColorName::White,
+        ColorName::Gray,
+    );
 
-        for split in splits.todo_splits.iter() {
-            term::writeln(split.display_incomplete(&dump), ColorName::White, None);
+    for split in splits.completed_splits.iter() {
+        match split.1 {
+            Some(time) => term::writeln(split.0.display_complete(time), ColorName::White, None),
+            None => term::writeln(
+                format!("{} = skipped", split.0.display_short()),
+                ColorName::Gray,
+                None,
+            ),
         }
+    }
 
-        thread::sleep(Duration::from_millis(12));
+    term::writeln(
+        "\n###########\nTODO Splits\n###########\n",
+        ColorName::White,
+        ColorName::Gray,
+    );
+
+    for split in splits.todo_splits.iter() {
+        term::writeln(split.display_incomplete(dump), ColorName::White, None);
     }
 }
@@ -0,0 +1,539 @@
+//! A WebSocket server broadcasting the state of the timer as JSON, for browser source overlays.
+//! Plain HTTP requests to `/` are answered with the bundled example overlay, so pointing a
+//! browser source at `http://<address>/` is enough to show the timer.
+
+use std::{
+    convert::TryInto,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, SyncSender},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use celeste_autosplit_tracer as cat;
+
+use celeste_autosplit_splits::{CurrentSplits, Split, TimerState, Times, TimingMethod};
+
+/// The overlay served to plain HTTP requests
+const OVERLAY_HTML: &str = include_str!("../overlay/overlay.html");
+
+/// The GUID every WebSocket handshake appends to the client's key
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// How many frames may wait to be written to a client before it is disconnected
+const QUEUE_LEN: usize = 64;
+/// Requests with longer headers are rejected
+const MAX_REQUEST: usize = 8192;
+/// How long a client may take to send its request before the connection is closed
+pub(crate) const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// A connected overlay, written to by its own thread so a slow one never blocks the timer
+#[derive(Debug)]
+struct Client {
+    // Frames waiting for the thread writing to this client
+    queue: SyncSender<Vec<u8>>,
+    // Only used to disconnect, which also stops the threads reading from and writing to it
+    stream: TcpStream,
+}
+
+impl Client {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        let mut writer = stream.try_clone()?;
+        let (queue, frames) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LEN);
+        thread::spawn(move || {
+            // Ends once the client is dropped, after writing what was still queued
+            for frame in frames {
+                if writer.write_all(&frame).is_err() {
+                    break;
+                }
+            }
+            let _ = writer.shutdown(Shutdown::Both);
+        });
+        Ok(Client { queue, stream })
+    }
+
+    /// Queues `frame` without waiting, returning false if the client is not keeping up or gone
+    fn send(&self, frame: Vec<u8>) -> bool {
+        self.queue.try_send(frame).is_ok()
+    }
+
+    fn disconnect(&self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+#[derive(Debug)]
+pub struct OverlayServer {
+    clients: Arc<Mutex<Vec<Arc<Client>>>>,
+    // The last message, sent to new clients and used to skip sending duplicates
+    last_message: Arc<Mutex<Option<String>>>,
+}
+
+impl OverlayServer {
+    /// Listens on `addr`, which is `host:port` or just a port to listen on localhost
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let listener = match addr.parse::<u16>() {
+            Ok(port) => TcpListener::bind(("127.0.0.1", port))?,
+            Err(_) => TcpListener::bind(addr)?,
+        };
+
+        let server = OverlayServer {
+            clients: Arc::new(Mutex::new(Vec::new())),
+            last_message: Arc::new(Mutex::new(None)),
+        };
+        let clients = Arc::clone(&server.clients);
+        let last_message = Arc::clone(&server.last_message);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("Unable to accept overlay connection: {}", e);
+                        continue;
+                    }
+                };
+                let clients = Arc::clone(&clients);
+                let last_message = Arc::clone(&last_message);
+                thread::spawn(move || {
+                    if let Err(e) = serve_connection(stream, &clients, &last_message) {
+                        if e.kind() != io::ErrorKind::UnexpectedEof {
+                            eprintln!("Overlay client disconnected: {}", e);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(server)
+    }
+
+    /// Sends `message` to every connected overlay, unless it is the same as the last one
+    pub fn broadcast(&self, message: String) {
+        {
+            let mut last_message = self.last_message.lock().expect("Unable to lock overlay");
+            if last_message.as_ref() == Some(&message) {
+                return;
+            }
+            *last_message = Some(message.clone());
+        }
+
+        let frame = frame(OPCODE_TEXT, message.as_bytes());
+        self.clients
+            .lock()
+            .expect("Unable to lock overlay")
+            .retain(|client| {
+                let sent = client.send(frame.clone());
+                if !sent {
+                    client.disconnect();
+                }
+                sent
+            });
+    }
+}
+
+fn serve_connection(
+    mut stream: TcpStream,
+    clients: &Mutex<Vec<Arc<Client>>>,
+    last_message: &Mutex<Option<String>>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let request = read_request(&mut stream)?;
+    let mut lines = request.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let key = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("Sec-WebSocket-Key")
+            .then(|| value.trim().to_string())
+    });
+
+    let key = match key {
+        Some(key) => key,
+        None => {
+            let response = if request_line.starts_with("GET / ") {
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+                    Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    OVERLAY_HTML.len(),
+                    OVERLAY_HTML
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            return stream.write_all(response.as_bytes());
+        }
+    };
+
+    let accept = accept_key(&key);
+    stream.write_all(
+        format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept
+        )
+        .as_bytes(),
+    )?;
+    // Overlays may stay quiet for as long as they are connected
+    stream.set_read_timeout(None)?;
+
+    let mut reader = stream.try_clone()?;
+    let client = Arc::new(Client::new(stream)?);
+    {
+        // Send the current state right away instead of waiting for it to change
+        let last_message = last_message.lock().expect("Unable to lock overlay");
+        let mut clients = clients.lock().expect("Unable to lock overlay");
+        if let Some(message) = last_message.as_ref() {
+            client.send(frame(OPCODE_TEXT, message.as_bytes()));
+        }
+        clients.push(Arc::clone(&client));
+    }
+
+    // Overlays only listen, but the connection still needs to answer pings and closes
+    let result = loop {
+        let (opcode, payload) = match read_frame(&mut reader) {
+            Ok(frame) => frame,
+            Err(e) => break Err(e),
+        };
+        match opcode {
+            OPCODE_PING => {
+                let answered = client.send(frame(OPCODE_PONG, &payload));
+                if !answered {
+                    break Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        "client is not keeping up",
+                    ));
+                }
+            }
+            OPCODE_CLOSE => {
+                // Dropping the client below closes the connection once this is written
+                client.send(frame(OPCODE_CLOSE, &payload));
+                break Ok(());
+            }
+            _ => {}
+        }
+    };
+
+    clients
+        .lock()
+        .expect("Unable to lock overlay")
+        .retain(|other| !Arc::ptr_eq(other, &client));
+    if result.is_err() {
+        client.disconnect();
+    }
+    result
+}
+
+/// Reads the HTTP request headers, up to the empty line ending them.  The stream should have a
+/// read timeout so clients can not hold the connection open by never finishing the request.
+pub(crate) fn read_request(stream: &mut TcpStream) -> io::Result<String> {
+    let mut request = Vec::new();
+    let mut byte = [0_u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request headers are too long",
+            ));
+        }
+        stream.read_exact(&mut byte)?;
+        request.push(byte[0]);
+    }
+    String::from_utf8(request).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads a single frame sent by a client, returning its opcode and unmasked payload
+fn read_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0_u8; 2];
+    stream.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0_u8; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as usize
+        }
+        127 => {
+            let mut len = [0_u8; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    if len > MAX_REQUEST {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too long", len),
+        ));
+    }
+
+    let mut mask = [0_u8; 4];
+    if masked {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0_u8; len];
+    stream.read_exact(&mut payload)?;
+    for (idx, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[idx % 4];
+    }
+
+    Ok((opcode, payload))
+}
+
+/// Builds an unmasked, unfragmented frame as servers send them
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(payload);
+    frame
+}
+
+/// SHA-1 as specified in RFC 3174, which the WebSocket handshake requires
+/// The `Sec-WebSocket-Accept` answer to a `Sec-WebSocket-Key`
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0_u32; 80];
+        for (word, bytes) in w.iter_mut().zip(chunk.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0_u8; 20];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+/// Standard base64 with padding
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The state of one runner as sent to overlays, inside `{"runners": [...]}`
+pub struct RunnerState<'a> {
+    // Stays the same for as long as the runner exists, unlike its position in the list
+    pub id: usize,
+    pub label: &'a str,
+    pub splits: &'a CurrentSplits,
+    pub dump: &'a cat::Dump,
+    pub state: TimerState,
+    pub method: TimingMethod,
+    // The times of the run, in milliseconds
+    pub times: Times,
+}
+
+impl RunnerState<'_> {
+    /// The time the run is timed by, in milliseconds
+    pub fn time(&self) -> u64 {
+        self.times.get(self.method)
+    }
+
+    /// Formats the state along with the [`cat::Dump`] it is based on as JSON.  Times are in
+    /// milliseconds, and deltas are against the `pb_time` of the split, or null if it has none.
+    pub fn to_json(&self) -> String {
+        let info = &self.dump.autosplitter_info;
+        let current_split = self.splits.todo_splits.first().map_or_else(
+            || "null".to_string(),
+            |split| {
+                format!(
+                    "{{\"index\":{},\"name\":{},\"delta\":{}}}",
+                    self.splits.completed_splits.len(),
+                    cat::json_string(&split.display_short()),
+                    json_delta(Some(self.time()), split.pb_time)
+                )
+            },
+        );
+        let next_split = self.splits.todo_splits.get(1).map_or_else(
+            || "null".to_string(),
+            |split| format!("{{\"name\":{}}}", cat::json_string(&split.display_short())),
+        );
+
+        format!(
+            "{{\"id\":{},\"label\":{},\"state\":\"{}\",\"timing_method\":\"{}\",\"time\":{},\
+            \"game_time\":{},\"real_time\":{},\"chapter_time\":{},\"file_time\":{},\"chapter\":{},\"room\":{},\"deaths\":{},\"current_split\":{},\"next_split\":{},\
+            \"splits\":{},\"dump\":{}}}",
+            self.id,
+            cat::json_string(self.label),
+            self.state.name(),
+            self.method.name(),
+            self.time(),
+            self.times.game,
+            self.times.real,
+            info.chapter_time(),
+            info.file_time(),
+            info.chapter,
+            cat::json_string(self.dump.level_name()),
+            self.dump.death_count,
+            current_split,
+            next_split,
+            self.splits_json(),
+            self.dump.to_json_line()
+        )
+    }
+
+    /// Formats every split, completed or not, as a JSON array
+    pub fn splits_json(&self) -> String {
+        let completed = self
+            .splits
+            .completed_splits
+            .iter()
+            .map(|(split, times)| split_json(split, *times, times.is_none(), self.method));
+        let todo = self
+            .splits
+            .todo_splits
+            .iter()
+            .map(|split| split_json(split, None, false, self.method));
+        format!("[{}]", completed.chain(todo).collect::<Vec<_>>().join(","))
+    }
+}
+
+fn split_json(split: &Split, times: Option<Times>, skipped: bool, method: TimingMethod) -> String {
+    let time = times.map(|times| times.get(method));
+    format!(
+        "{{\"name\":{},\"time\":{},\"game_time\":{},\"real_time\":{},\"skipped\":{},\
+        \"pb_time\":{},\"delta\":{}}}",
+        cat::json_string(&split.display_short()),
+        json_option(time),
+        json_option(times.map(|times| times.game)),
+        json_option(times.map(|times| times.real)),
+        skipped,
+        json_option(split.pb_time),
+        json_delta(time, split.pb_time)
+    )
+}
+
+fn json_option(value: Option<u64>) -> String {
+    value.map_or_else(|| "null".to_string(), |value| value.to_string())
+}
+
+fn json_delta(time: Option<u64>, pb_time: Option<u64>) -> String {
+    match (time, pb_time) {
+        (Some(time), Some(pb_time)) => (time as i64 - pb_time as i64).to_string(),
+        _ => "null".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn accepts_the_rfc_6455_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn sha1_digests() {
+        assert_eq!(hex(&sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Long enough that the padding needs a second block
+        assert_eq!(
+            hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn base64_padding() {
+        let encoded = [
+            "", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy",
+        ];
+        for (len, expected) in encoded.iter().enumerate() {
+            assert_eq!(base64(&b"foobar"[..len]), *expected);
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Plain text files for streaming software to read, one value per file.
+//!
+//! - `timer.txt` the time the run is timed by
+//! - `game_time.txt` and `real_time.txt` the in-game and the real time of the run
+//! - `chapter_time.txt` the time in the current chapter
+//! - `current_split.txt` the name of the split being run, empty once the run ended
+//! - `delta.txt` the difference to the personal best at the last completed split that has one
+//! - `deaths.txt` the total deaths of the save file
+//! - `room.txt` the current room
+//! - `berries.txt` the strawberries of the chapter in individual level runs, of the file otherwise
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use celeste_autosplit_splits::{format_time, format_time_with_units};
+
+use crate::overlay::RunnerState;
+
+/// How times are written, matching the helpers in [`celeste_autosplit_splits`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// `01:23.456`
+    Plain,
+    /// `01m 23.456s`
+    Units,
+}
+
+impl TimeFormat {
+    pub const NAMES: &'static [&'static str] = &["plain", "units"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "plain" => Some(TimeFormat::Plain),
+            "units" => Some(TimeFormat::Units),
+            _ => None,
+        }
+    }
+
+    fn format(self, ms: u64) -> String {
+        let duration = Duration::from_millis(ms);
+        match self {
+            TimeFormat::Plain => format_time(duration),
+            TimeFormat::Units => format_time_with_units(duration),
+        }
+    }
+
+    fn format_delta(self, delta: i64) -> String {
+        let sign = if delta < 0 { '-' } else { '+' };
+        format!("{}{}", sign, self.format(delta.unsigned_abs()))
+    }
+}
+
+#[derive(Debug)]
+pub struct TextFiles {
+    dir: PathBuf,
+    format: TimeFormat,
+    // The contents written last, so unchanged files are left alone
+    written: HashMap<&'static str, String>,
+}
+
+impl TextFiles {
+    /// Writes the files into `dir`, creating it if needed
+    pub fn create<P: AsRef<Path>>(dir: P, format: TimeFormat) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(TextFiles {
+            dir,
+            format,
+            written: HashMap::new(),
+        })
+    }
+
+    /// Rewrites every file whose contents changed since the last update
+    pub fn update(&mut self, state: &RunnerState, chapter_timed: bool) -> io::Result<()> {
+        let info = &state.dump.autosplitter_info;
+        let current_split = state
+            .splits
+            .todo_splits
+            .first()
+            .map(|split| split.display_short())
+            .unwrap_or_default();
+        let delta = state
+            .splits
+            .completed_splits
+            .iter()
+            .rev()
+            .find_map(|(split, times)| {
+                Some((*times)?.get(state.method) as i64 - split.pb_time? as i64)
+            })
+            .map(|delta| self.format.format_delta(delta))
+            .unwrap_or_default();
+        let berries = if chapter_timed {
+            info.chapter_strawberries
+        } else {
+            info.file_strawberries
+        };
+
+        let files = vec![
+            ("timer.txt", self.format.format(state.time())),
+            ("game_time.txt", self.format.format(state.times.game)),
+            ("real_time.txt", self.format.format(state.times.real)),
+            ("chapter_time.txt", self.format.format(info.chapter_time())),
+            ("current_split.txt", current_split),
+            ("delta.txt", delta),
+            ("deaths.txt", state.dump.death_count.to_string()),
+            ("room.txt", state.dump.level_name().to_string()),
+            ("berries.txt", berries.to_string()),
+        ];
+        for (name, contents) in files {
+            if self.written.get(name) == Some(&contents) {
+                continue;
+            }
+            self.write(name, &contents)?;
+            self.written.insert(name, contents);
+        }
+        Ok(())
+    }
+
+    /// Replaces `name` in one step, so readers never see a partially written file
+    fn write(&self, name: &str, contents: &str) -> io::Result<()> {
+        let temp = self.dir.join(format!(".{}.tmp", name));
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, self.dir.join(name))
+    }
+}
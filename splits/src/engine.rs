@@ -0,0 +1,449 @@
+use std::{mem, time::Instant};
+
+use celeste_autosplit_tracer as cat;
+
+use crate::{
+    Attempt, ResetRule, SplitMode, Splits, StartRule, Timer, TimerState, Times, TimingMethod,
+};
+
+/// Something that changes the progress of a run, either decided by the [`SplitEngine`] or asked
+/// for from outside, like over the API
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerAction {
+    Start,
+    Split,
+    Skip,
+    Undo,
+    Pause,
+    Resume,
+    Reset,
+}
+
+/// Runs the splits of one game, starting, resetting and completing the splits of the run as the
+/// dumps of the game call for it.
+///
+/// Every operation takes the dump along with the [`Instant`] it was read at.  The real time is
+/// counted from those rather than from anything in the game, so it keeps going while the game
+/// can not be read.
+#[derive(Debug)]
+pub struct SplitEngine {
+    timer: Timer,
+    mode: SplitMode,
+    start_rule: StartRule,
+    reset_rules: Vec<ResetRule>,
+    method: TimingMethod,
+    // Either the chapter or the file time, recorded along with the real time
+    game_clock: TimingMethod,
+    // What the real time clock counts from
+    epoch: Instant,
+    // The dump of the previous update and when it was read, to tell what changed since
+    last: Option<(cat::Dump, Instant)>,
+    // Runs that were reset since the last call to `take_attempts`
+    attempts: Vec<Attempt>,
+}
+
+impl SplitEngine {
+    pub fn new(splits: &Splits) -> Self {
+        SplitEngine {
+            timer: Timer::new(splits.splits.clone()),
+            mode: splits.split_mode,
+            start_rule: splits.start_rule(),
+            reset_rules: splits.reset_rules(),
+            method: splits.timing_method(),
+            game_clock: splits.game_clock(),
+            epoch: Instant::now(),
+            last: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    pub fn timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    pub fn mode(&self) -> SplitMode {
+        self.mode
+    }
+
+    /// When the run starts by itself
+    pub fn start_rule(&self) -> &StartRule {
+        &self.start_rule
+    }
+
+    /// When the run is reset by itself
+    pub fn reset_rules(&self) -> &[ResetRule] {
+        &self.reset_rules
+    }
+
+    /// Hands over the runs that were reset since the last call, to be kept in the history
+    pub fn take_attempts(&mut self) -> Vec<Attempt> {
+        mem::take(&mut self.attempts)
+    }
+
+    /// The clock whose time counts for the run
+    pub fn timing_method(&self) -> TimingMethod {
+        self.method
+    }
+
+    /// The in-game clock the run is timed on besides the real time
+    pub fn game_clock(&self) -> TimingMethod {
+        self.game_clock
+    }
+
+    /// Reads both clocks, in milliseconds
+    fn clock(&self, dump: &cat::Dump, now: Instant) -> Times {
+        let info = &dump.autosplitter_info;
+        let game = match self.game_clock {
+            TimingMethod::ChapterTime => info.chapter_time(),
+            TimingMethod::FileTime | TimingMethod::RealTime => info.file_time(),
+        };
+        let real = now.saturating_duration_since(self.epoch).as_millis() as u64;
+        Times { game, real }
+    }
+
+    /// Reads both clocks as they were at the previous update
+    fn last_clock(&self) -> Option<Times> {
+        let (last, last_read) = self.last.as_ref()?;
+        Some(self.clock(last, *last_read))
+    }
+
+    /// The times of the run at `dump`, in milliseconds
+    pub fn times(&self, dump: &cat::Dump, now: Instant) -> Times {
+        self.timer.time(self.clock(dump, now))
+    }
+
+    /// Resets and starts the run by its rules and completes the splits accomplished in `dump`,
+    /// returning the actions that were taken.  Individual level runs only split in their own
+    /// chapter and side.
+    pub fn update(&mut self, dump: &cat::Dump, now: Instant) -> Vec<TimerAction> {
+        let mut actions = Vec::new();
+        // Before starting, so a restart resets the old run and starts the new one at once
+        if self.auto_reset(dump, now) {
+            actions.push(TimerAction::Reset);
+        }
+        if self.auto_start(dump, now) {
+            actions.push(TimerAction::Start);
+        }
+        self.last = Some((dump.clone(), now));
+
+        let info = &dump.autosplitter_info;
+        if !self.mode.is_in_scope(info.chapter, info.mode) {
+            return actions;
+        }
+        while let Some(split) = self.timer.splits().todo_splits.first() {
+            if !split.is_accomplished(dump) || !self.apply(TimerAction::Split, dump, now) {
+                break;
+            }
+            actions.push(TimerAction::Split);
+        }
+        actions
+    }
+
+    /// Resets the run if any reset rule is met since the last update
+    fn auto_reset(&mut self, dump: &cat::Dump, now: Instant) -> bool {
+        if self.timer.state() == TimerState::NotRunning {
+            return false;
+        }
+        let triggered = match &self.last {
+            Some((last, _)) => self
+                .reset_rules
+                .iter()
+                .any(|rule| rule.triggered(self.mode, last, dump)),
+            None => false,
+        };
+        triggered && self.apply(TimerAction::Reset, dump, now)
+    }
+
+    /// Starts the run if the start rule is met since the last update, at the moment the game
+    /// clock shows it was met rather than when it was noticed
+    fn auto_start(&mut self, dump: &cat::Dump, now: Instant) -> bool {
+        if self.timer.state() != TimerState::NotRunning {
+            return false;
+        }
+        // Only the first update has no previous dump, so this never starts again after a reset
+        if self.start_rule == StartRule::OnLaunch {
+            return self.last.is_none() && self.apply(TimerAction::Start, dump, now);
+        }
+        let (last, last_read) = match &self.last {
+            Some(last) => last,
+            None => return false,
+        };
+        let elapsed = match self.start_rule.started(self.mode, last, dump) {
+            Some(elapsed) => elapsed,
+            None => return false,
+        };
+        // Going by the real time, the game clock started no earlier than the last update
+        let since_last = now.saturating_duration_since(*last_read).as_millis() as u64;
+        let clock = self.clock(dump, now);
+        self.timer.start(Times {
+            game: clock.game.saturating_sub(elapsed),
+            real: clock.real.saturating_sub(elapsed.min(since_last)),
+        })
+    }
+
+    /// Applies `action` at the time of `dump`, returning false if it did not change anything.
+    /// Runs that are reset are kept for [`SplitEngine::take_attempts`].
+    pub fn apply(&mut self, action: TimerAction, dump: &cat::Dump, now: Instant) -> bool {
+        let now = self.clock(dump, now);
+        match action {
+            // The in-game clocks count from zero themselves, whenever the run is started
+            TimerAction::Start => self.timer.start(Times { game: 0, ..now }),
+            TimerAction::Split => self.timer.split(now),
+            TimerAction::Skip => self.timer.skip(),
+            TimerAction::Undo => self.timer.undo(),
+            TimerAction::Pause => self.timer.pause(now),
+            TimerAction::Resume => self.timer.resume(now),
+            // The dump that caused a reset may already show the next run, like a restarted
+            // chapter, so the attempt ends at the last dump before it
+            TimerAction::Reset => match self.timer.attempt(self.last_clock().unwrap_or(now)) {
+                Some(attempt) => {
+                    self.attempts.push(attempt);
+                    self.timer.reset()
+                }
+                None => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{Side, Split, SplitKind};
+
+    /// The parts of the game state the engine looks at
+    #[derive(Clone, Copy, Default)]
+    struct State {
+        chapter: i32,
+        started: bool,
+        complete: bool,
+        chapter_ms: u64,
+        file_ms: u64,
+        room: &'static str,
+    }
+
+    impl State {
+        const MAP: State = State {
+            chapter: -1,
+            started: false,
+            complete: false,
+            chapter_ms: 0,
+            file_ms: 60_000,
+            room: "",
+        };
+
+        fn in_chapter(chapter_ms: u64, room: &'static str) -> State {
+            State {
+                chapter: 1,
+                started: true,
+                chapter_ms,
+                file_ms: 60_000 + chapter_ms,
+                room,
+                ..State::MAP
+            }
+        }
+
+        fn dump(&self) -> cat::Dump {
+            let mut dump = cat::Dump::default();
+            let info = &mut dump.autosplitter_info;
+            info.chapter = self.chapter;
+            info.chapter_started = self.started;
+            info.chapter_complete = self.complete;
+            info.set_chapter_time(self.chapter_ms);
+            info.set_file_time(self.file_ms);
+            dump.set_level_name(self.room);
+            dump
+        }
+    }
+
+    /// Feeds states to an engine for chapter 1 A-Side with the default rules
+    struct Run {
+        engine: SplitEngine,
+        start: Instant,
+    }
+
+    impl Run {
+        fn new(split_kinds: Vec<SplitKind>) -> Self {
+            let mode = SplitMode::IndividualLevel {
+                chapter: 1,
+                side: Side::A,
+            };
+            Run::with_rule(mode, None, split_kinds)
+        }
+
+        fn with_rule(
+            split_mode: SplitMode,
+            start_rule: Option<StartRule>,
+            split_kinds: Vec<SplitKind>,
+        ) -> Self {
+            let splits = split_kinds
+                .into_iter()
+                .map(|split_kind| Split {
+                    name: None,
+                    chapter: 1,
+                    split_kind,
+                    pb_time: None,
+                })
+                .collect();
+            let engine = SplitEngine::new(&Splits {
+                split_mode,
+                timing_method: None,
+                start_rule,
+                reset_rules: None,
+                splits,
+            });
+            Run {
+                engine,
+                start: Instant::now(),
+            }
+        }
+
+        /// Updates the engine with `state` read `ms` milliseconds into the test
+        fn poll(&mut self, ms: u64, state: State) -> Vec<TimerAction> {
+            let now = self.start + Duration::from_millis(ms);
+            self.engine.update(&state.dump(), now)
+        }
+
+        /// The game time of the run at `state`
+        fn game_time(&self, ms: u64, state: State) -> u64 {
+            let now = self.start + Duration::from_millis(ms);
+            self.engine.times(&state.dump(), now).game
+        }
+
+        /// Starts the run `chapter_ms` into the chapter
+        fn started(split_kinds: Vec<SplitKind>, chapter_ms: u64) -> Self {
+            let mut run = Run::new(split_kinds);
+            assert_eq!(run.poll(0, State::MAP), []);
+            let state = State::in_chapter(chapter_ms, "1");
+            assert_eq!(run.poll(chapter_ms, state), [TimerAction::Start]);
+            run
+        }
+    }
+
+    #[test]
+    fn splits_in_order_and_ends_after_the_last() {
+        let kinds = vec![
+            SplitKind::Level("2".to_string()),
+            SplitKind::ChapterComplete,
+        ];
+        let mut run = Run::started(kinds, 0);
+
+        // The chapter completing first does not skip the room split
+        assert_eq!(run.poll(500, State::in_chapter(500, "1")), []);
+        assert_eq!(
+            run.poll(1_000, State::in_chapter(1_000, "2")),
+            [TimerAction::Split]
+        );
+        let complete = State {
+            complete: true,
+            ..State::in_chapter(2_000, "2")
+        };
+        assert_eq!(run.poll(2_000, complete), [TimerAction::Split]);
+        assert_eq!(run.engine.timer().state(), TimerState::Ended);
+        assert_eq!(run.game_time(3_000, State::in_chapter(3_000, "2")), 2_000);
+
+        let times = run
+            .engine
+            .timer()
+            .splits()
+            .completed_splits
+            .iter()
+            .map(|(_, times)| times.map(|times| times.game))
+            .collect::<Vec<_>>();
+        assert_eq!(times, [Some(1_000), Some(2_000)]);
+    }
+
+    #[test]
+    fn starts_from_the_chapter_time_when_the_chapter_starts() {
+        let mut run = Run::new(vec![SplitKind::ChapterComplete]);
+        assert_eq!(run.poll(0, State::MAP), []);
+        // Other chapters do not start the run
+        let other = State {
+            chapter: 2,
+            ..State::in_chapter(10, "1")
+        };
+        assert_eq!(run.poll(10, other), []);
+        assert_eq!(run.poll(20, State::MAP), []);
+        assert_eq!(run.engine.timer().state(), TimerState::NotRunning);
+
+        let state = State::in_chapter(40, "1");
+        assert_eq!(run.poll(60, state), [TimerAction::Start]);
+        assert_eq!(run.engine.timer().state(), TimerState::Running);
+        // The chapter had been running for 40ms when the start was noticed
+        assert_eq!(run.game_time(60, state), 40);
+    }
+
+    #[test]
+    fn returning_to_the_map_resets_and_keeps_the_attempt_as_it_was() {
+        let mut run = Run::started(vec![SplitKind::ChapterComplete], 0);
+        assert_eq!(run.poll(3_000, State::in_chapter(3_000, "1")), []);
+
+        assert_eq!(run.poll(3_100, State::MAP), [TimerAction::Reset]);
+        assert_eq!(run.engine.timer().state(), TimerState::NotRunning);
+
+        let attempts = run.engine.take_attempts();
+        assert_eq!(attempts.len(), 1);
+        assert!(!attempts[0].finished);
+        // The map has no chapter time, so the attempt ends at the last dump in the chapter
+        assert_eq!(attempts[0].time.game, 3_000);
+        assert!(run.engine.take_attempts().is_empty());
+    }
+
+    #[test]
+    fn restarting_the_chapter_between_polls_starts_a_new_run() {
+        let mut run = Run::started(vec![SplitKind::ChapterComplete], 0);
+        assert_eq!(run.poll(5_000, State::in_chapter(5_000, "1")), []);
+
+        // The restart never shows the map, so the chapter stays started throughout
+        let restarted = State::in_chapter(20, "1");
+        assert_eq!(
+            run.poll(5_030, restarted),
+            [TimerAction::Reset, TimerAction::Start]
+        );
+        assert_eq!(run.engine.timer().state(), TimerState::Running);
+        assert_eq!(run.game_time(5_030, restarted), 20);
+
+        let attempts = run.engine.take_attempts();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].time.game, 5_000);
+    }
+
+    #[test]
+    fn manual_runs_only_start_when_asked_to() {
+        let mut run = Run::with_rule(SplitMode::Custom, None, vec![SplitKind::ChapterComplete]);
+        assert_eq!(*run.engine.start_rule(), StartRule::Manual);
+        assert_eq!(run.poll(0, State::MAP), []);
+        assert_eq!(run.poll(10, State::in_chapter(10, "1")), []);
+        assert_eq!(run.engine.timer().state(), TimerState::NotRunning);
+
+        let state = State::in_chapter(20, "1");
+        assert!(run
+            .engine
+            .apply(TimerAction::Start, &state.dump(), run.start));
+        assert_eq!(run.engine.timer().state(), TimerState::Running);
+        assert!(run
+            .engine
+            .apply(TimerAction::Reset, &state.dump(), run.start));
+        // Nothing starts the run again but another request
+        assert_eq!(run.poll(30, State::in_chapter(30, "1")), []);
+        assert_eq!(run.poll(40, State::MAP), []);
+        assert_eq!(run.engine.timer().state(), TimerState::NotRunning);
+    }
+
+    #[test]
+    fn runs_started_on_launch_wait_for_a_manual_start_after_a_reset() {
+        let rule = Some(StartRule::OnLaunch);
+        let mut run = Run::with_rule(SplitMode::Custom, rule, vec![SplitKind::ChapterComplete]);
+        assert_eq!(run.poll(0, State::MAP), [TimerAction::Start]);
+        assert_eq!(run.engine.timer().state(), TimerState::Running);
+
+        assert!(run
+            .engine
+            .apply(TimerAction::Reset, &State::MAP.dump(), run.start));
+        assert_eq!(run.poll(10, State::MAP), []);
+        assert_eq!(run.poll(20, State::in_chapter(20, "1")), []);
+        assert_eq!(run.engine.timer().state(), TimerState::NotRunning);
+    }
+}
@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+use crate::Times;
+
+/// A run that ended or was reset, as kept in the history of a splits file
+// The fields are in this order so an empty `splits` array comes before the `time` table in TOML
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attempt {
+    /// Whether every split was completed
+    pub finished: bool,
+    /// The splits that were completed or skipped, in order
+    pub splits: Vec<AttemptSplit>,
+    /// The times of the run when it was reset
+    pub time: Times,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttemptSplit {
+    pub name: String,
+    // None if the split was skipped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<Times>,
+}
+
+/// Every attempt at a splits file, oldest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    pub attempts: Vec<Attempt>,
+}
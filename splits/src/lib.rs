@@ -0,0 +1,19 @@
+//! Decides when the splits of a run are accomplished from the state of the game.
+//!
+//! Nothing here does any I/O, so the same logic can drive the terminal timer, overlays,
+//! headless runners and tests alike.
+
+mod engine;
+mod history;
+mod mode;
+mod rules;
+mod split;
+mod time;
+mod timer;
+pub use crate::engine::*;
+pub use crate::history::*;
+pub use crate::mode::*;
+pub use crate::rules::*;
+pub use crate::split::*;
+pub use crate::time::*;
+pub use crate::timer::*;
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// The side of a chapter, as reported in the `mode` of the game
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Side {
+    A,
+    B,
+    C,
+}
+
+impl Side {
+    pub const ALL: &'static [Side] = &[Side::A, Side::B, Side::C];
+
+    /// The `mode` the game reports while playing this side
+    pub fn mode(self) -> i32 {
+        match self {
+            Side::A => 0,
+            Side::B => 1,
+            Side::C => 2,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Side::A => "A-Side",
+            Side::B => "B-Side",
+            Side::C => "C-Side",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A full game category
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Category {
+    #[serde(rename = "Any%")]
+    AnyPercent,
+    #[serde(rename = "ARB")]
+    AllRedBerries,
+    #[serde(rename = "True Ending")]
+    TrueEnding,
+    #[serde(rename = "All Cassettes")]
+    AllCassettes,
+    #[serde(rename = "All Hearts")]
+    AllHearts,
+    #[serde(rename = "100%")]
+    HundredPercent,
+}
+
+impl Category {
+    pub const ALL: &'static [Category] = &[
+        Category::AnyPercent,
+        Category::AllRedBerries,
+        Category::TrueEnding,
+        Category::AllCassettes,
+        Category::AllHearts,
+        Category::HundredPercent,
+    ];
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Category::AnyPercent => "Any%",
+            Category::AllRedBerries => "ARB",
+            Category::TrueEnding => "True Ending",
+            Category::AllCassettes => "All Cassettes",
+            Category::AllHearts => "All Hearts",
+            Category::HundredPercent => "100%",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The clock whose time counts for a run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimingMethod {
+    /// The in-game time of the current chapter
+    ChapterTime,
+    /// The in-game time of the save file
+    FileTime,
+    /// The wall clock, counted from when the run started
+    RealTime,
+}
+
+impl TimingMethod {
+    pub fn name(self) -> &'static str {
+        match self {
+            TimingMethod::ChapterTime => "chapter_time",
+            TimingMethod::FileTime => "file_time",
+            TimingMethod::RealTime => "real_time",
+        }
+    }
+}
+
+impl fmt::Display for TimingMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TimingMethod::ChapterTime => "Chapter time",
+            TimingMethod::FileTime => "File time",
+            TimingMethod::RealTime => "Real time",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What a splits file is for, deciding how the run is timed and which splits make sense
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum SplitMode {
+    /// A single side of one chapter, timed by the chapter time
+    IndividualLevel { chapter: i32, side: Side },
+    /// A run through the game on one save file, timed by the file time
+    FullGame { category: Category },
+    /// Anything else, timed by the file time without any checks
+    Custom,
+}
+
+impl SplitMode {
+    /// The in-game clock runs are timed by unless the splits choose otherwise
+    pub fn default_timing_method(&self) -> TimingMethod {
+        match self {
+            SplitMode::IndividualLevel { .. } => TimingMethod::ChapterTime,
+            SplitMode::FullGame { .. } | SplitMode::Custom => TimingMethod::FileTime,
+        }
+    }
+
+    /// Whether the game is in the chapter and side the run is about.  Always true outside of
+    /// individual level runs.
+    pub fn is_in_scope(&self, chapter: i32, mode: i32) -> bool {
+        match *self {
+            SplitMode::IndividualLevel {
+                chapter: il_chapter,
+                side,
+            } => chapter == il_chapter && mode == side.mode(),
+            SplitMode::FullGame { .. } | SplitMode::Custom => true,
+        }
+    }
+
+    /// Reads a split mode, accepting the `['IL', <chapter>]` pairs of older splits files as well
+    pub fn deserialize_compat<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Compat {
+            Legacy(String, i32),
+            Mode(SplitMode),
+        }
+
+        Ok(match Compat::deserialize(deserializer)? {
+            Compat::Mode(mode) => mode,
+            Compat::Legacy(kind, chapter) if kind == "IL" => SplitMode::IndividualLevel {
+                chapter,
+                side: Side::A,
+            },
+            Compat::Legacy(kind, _) if kind == "Any%" => SplitMode::FullGame {
+                category: Category::AnyPercent,
+            },
+            Compat::Legacy(..) => SplitMode::Custom,
+        })
+    }
+}
+
+impl fmt::Display for SplitMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitMode::IndividualLevel { chapter, side } => {
+                write!(f, "Individual level, chapter {} {}", chapter, side)
+            }
+            SplitMode::FullGame { category } => write!(f, "Full game, {}", category),
+            SplitMode::Custom => f.write_str("Custom"),
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::fmt;
+
+use celeste_autosplit_tracer as cat;
+use serde::{Deserialize, Serialize};
+
+use crate::SplitMode;
+
+/// When a run starts by itself
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum StartRule {
+    /// When a chapter starts or is restarted, which has to be the one of individual level runs
+    ChapterStart,
+    /// When the file time starts counting, as it does once a file is created or picked on the
+    /// file select screen
+    FileSelect,
+    /// When the game moves out of `room`
+    LeaveRoom { room: String },
+    /// Never by itself, only when asked to over the API or by an auto splitter
+    Manual,
+    /// Once as soon as the timer is launched, and manually after a reset
+    OnLaunch,
+}
+
+impl StartRule {
+    /// The rule runs of `mode` start by unless the splits choose otherwise
+    pub fn default_for(mode: SplitMode) -> Self {
+        match mode {
+            SplitMode::IndividualLevel { .. } => StartRule::ChapterStart,
+            SplitMode::FullGame { .. } => StartRule::FileSelect,
+            SplitMode::Custom => StartRule::Manual,
+        }
+    }
+
+    /// Checks whether the run starts between `prev` and `next`.  Returns how long the game clock
+    /// has been running since the moment the run started at, in milliseconds.  Runs started on
+    /// launch do not wait for anything to change, so they are started by the
+    /// [`SplitEngine`](crate::SplitEngine) itself.  Manual runs never start by themselves.
+    pub fn started(&self, mode: SplitMode, prev: &cat::Dump, next: &cat::Dump) -> Option<u64> {
+        let (before, after) = (&prev.autosplitter_info, &next.autosplitter_info);
+        match self {
+            StartRule::ChapterStart => {
+                let started = after.chapter_started && !before.chapter_started;
+                // A restart between two polls never leaves the chapter, so only the chapter
+                // time going backwards shows it, as for `ResetRule::ChapterTimeBackwards`
+                let restarted = ResetRule::ChapterTimeBackwards.triggered(mode, prev, next);
+                if (started || restarted) && mode.is_in_scope(after.chapter, after.mode) {
+                    return Some(after.chapter_time());
+                }
+            }
+            StartRule::FileSelect => {
+                if before.file_time() == 0 && after.file_time() > 0 {
+                    return Some(after.file_time());
+                }
+            }
+            StartRule::LeaveRoom { room } => {
+                let left = prev.level_name() == room && next.level_name() != room;
+                if left && !next.level_name().is_empty() {
+                    return Some(0);
+                }
+            }
+            StartRule::Manual | StartRule::OnLaunch => {}
+        }
+        None
+    }
+}
+
+impl fmt::Display for StartRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StartRule::ChapterStart => f.write_str("chapter start"),
+            StartRule::FileSelect => f.write_str("file select"),
+            StartRule::LeaveRoom { room } => write!(f, "leaving room {}", room),
+            StartRule::Manual => f.write_str("manual start"),
+            StartRule::OnLaunch => f.write_str("on launch"),
+        }
+    }
+}
+
+/// When a run is reset by itself, to be started again by its [`StartRule`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ResetRule {
+    /// When the chapter time goes backwards within a chapter, as it does when it is restarted
+    ChapterTimeBackwards,
+    /// When the chapter starts again, which has to be the one of individual level runs
+    ChapterRestart,
+    /// When the game leaves a chapter for the map without completing it
+    ReturnToMap,
+    /// When the game leaves a chapter through save and quit, which unloads the save file so the
+    /// file time reads zero
+    SaveAndQuit,
+    /// When the game comes back to `room` from another room, usually the first of the chapter
+    EnterRoom { room: String },
+}
+
+impl ResetRule {
+    /// The rules runs of `mode` are reset by unless the splits choose otherwise.  Only individual
+    /// levels are reset by themselves.
+    pub fn defaults_for(mode: SplitMode) -> Vec<Self> {
+        match mode {
+            SplitMode::IndividualLevel { .. } => vec![
+                ResetRule::ChapterTimeBackwards,
+                ResetRule::ChapterRestart,
+                ResetRule::ReturnToMap,
+                ResetRule::SaveAndQuit,
+            ],
+            SplitMode::FullGame { .. } | SplitMode::Custom => Vec::new(),
+        }
+    }
+
+    /// Checks whether the run is reset between `prev` and `next`
+    pub fn triggered(&self, mode: SplitMode, prev: &cat::Dump, next: &cat::Dump) -> bool {
+        let (before, after) = (&prev.autosplitter_info, &next.autosplitter_info);
+        let left_chapter = before.chapter != -1 && after.chapter == -1;
+        match self {
+            ResetRule::ChapterTimeBackwards => {
+                let in_chapter = before.chapter != -1 && after.chapter != -1;
+                in_chapter && after.chapter_time() < before.chapter_time()
+            }
+            ResetRule::ChapterRestart => {
+                let started = after.chapter_started && !before.chapter_started;
+                started && mode.is_in_scope(after.chapter, after.mode)
+            }
+            ResetRule::ReturnToMap => {
+                left_chapter && !before.chapter_complete && after.file_time() > 0
+            }
+            ResetRule::SaveAndQuit => left_chapter && after.file_time() == 0,
+            ResetRule::EnterRoom { room } => {
+                let entered = next.level_name() == room && prev.level_name() != room;
+                entered && !prev.level_name().is_empty()
+            }
+        }
+    }
+}
+
+impl fmt::Display for ResetRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResetRule::ChapterTimeBackwards => f.write_str("chapter time going backwards"),
+            ResetRule::ChapterRestart => f.write_str("chapter restart"),
+            ResetRule::ReturnToMap => f.write_str("return to map"),
+            ResetRule::SaveAndQuit => f.write_str("save and quit"),
+            ResetRule::EnterRoom { room } => write!(f, "entering room {}", room),
+        }
+    }
+}
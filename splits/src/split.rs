@@ -0,0 +1,263 @@
+use std::{error::Error, fmt, time::Duration};
+
+use celeste_autosplit_tracer as cat;
+use serde::{Deserialize, Serialize};
+
+use crate::{format_time_with_units, ResetRule, SplitMode, StartRule, Times, TimingMethod};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "kind_data")]
+pub enum SplitKind {
+    Heart,
+    Casette,
+    Berries(i32),
+    Level(String),
+    ChapterComplete,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Split {
+    pub name: Option<String>,
+    pub chapter: i32,
+    pub split_kind: SplitKind,
+    // The time of this split in the personal best in milliseconds, for showing deltas
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pb_time: Option<u64>,
+}
+
+impl Split {
+    pub fn is_accomplished(&self, info: &cat::Dump) -> bool {
+        match info.autosplitter_info.chapter == self.chapter {
+            true => match &self.split_kind {
+                SplitKind::Level(lvl) => lvl == info.level_name(),
+                SplitKind::Heart => info.autosplitter_info.chapter_heart,
+                SplitKind::Casette => info.autosplitter_info.chapter_cassette,
+                &SplitKind::Berries(bewwy_count) => {
+                    bewwy_count == info.autosplitter_info.chapter_strawberries
+                }
+                SplitKind::ChapterComplete => info.autosplitter_info.chapter_complete,
+            },
+            false => false,
+        }
+    }
+
+    pub fn display_long(&self) -> String {
+        let name = if let Some(name) = &self.name {
+            format!("{}\n  ", name)
+        } else {
+            String::new()
+        };
+
+        let split_info = match &self.split_kind {
+            SplitKind::Heart => "Heart".to_string(),
+            SplitKind::Casette => "Casette".to_string(),
+            SplitKind::Berries(num) => format!("{} Berries", num),
+            SplitKind::Level(room) => format!("Room {}", room),
+            SplitKind::ChapterComplete => "Complete".to_string(),
+        };
+        format!("{}Cp. {} {}", name, self.chapter, split_info)
+    }
+
+    pub fn display_short(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.to_string();
+        }
+
+        let split_info = match &self.split_kind {
+            SplitKind::Heart => "Heart".to_string(),
+            SplitKind::Casette => "Casette".to_string(),
+            SplitKind::Berries(num) => format!("{} Berries", num),
+            SplitKind::Level(room) => format!("Room {}", room),
+            SplitKind::ChapterComplete => "Complete".to_string(),
+        };
+        format!("Cp. {} {}", self.chapter, split_info)
+    }
+
+    pub fn display_incomplete(&self, info: &cat::Dump) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+
+        let split_kind = match &self.split_kind {
+            SplitKind::Level(level) => level,
+            SplitKind::Heart => "Heart",
+            SplitKind::Casette => "Casette",
+            SplitKind::ChapterComplete => "Complete",
+            SplitKind::Berries(num_berries) => {
+                return format!(
+                    "{}/{} Berries",
+                    info.autosplitter_info.file_strawberries, num_berries
+                );
+            }
+        };
+        format!("Ch.{}: {}", self.chapter, split_kind,)
+    }
+
+    pub fn display_complete(&self, times: Times) -> String {
+        let finish_time = format!(
+            "{} IGT / {} RTA",
+            format_time_with_units(Duration::from_millis(times.game)),
+            format_time_with_units(Duration::from_millis(times.real))
+        );
+
+        if let Some(name) = &self.name {
+            return format!("{} = {}", name, finish_time);
+        }
+
+        let split_kind = match &self.split_kind {
+            SplitKind::Level(level) => level,
+            SplitKind::Heart => "Heart",
+            SplitKind::Casette => "Casette",
+            SplitKind::ChapterComplete => "Complete",
+            SplitKind::Berries(num_berries) => {
+                return format!("{}/{} Berries = {}", num_berries, num_berries, finish_time);
+            }
+        };
+        format!("Ch.{}: {} = {}", self.chapter, split_kind, finish_time)
+    }
+}
+
+#[derive(Debug)]
+pub struct CurrentSplits {
+    // Each with the times it was completed at, or None if it was skipped
+    pub completed_splits: Vec<(Split, Option<Times>)>,
+    pub todo_splits: Vec<Split>,
+}
+
+impl CurrentSplits {
+    pub fn new(splits: Vec<Split>) -> Self {
+        CurrentSplits {
+            completed_splits: vec![],
+            todo_splits: splits,
+        }
+    }
+
+    /// Completes the current split at `time`, returning false if there is none left
+    pub fn split(&mut self, time: Times) -> bool {
+        self.complete(Some(time))
+    }
+
+    /// Moves past the current split without a time, returning false if there is none left
+    pub fn skip(&mut self) -> bool {
+        self.complete(None)
+    }
+
+    fn complete(&mut self, time: Option<Times>) -> bool {
+        if self.todo_splits.is_empty() {
+            return false;
+        }
+        let split = self.todo_splits.remove(0);
+        self.completed_splits.push((split, time));
+        true
+    }
+
+    /// Makes the last completed split current again, returning false if there is none
+    pub fn undo(&mut self) -> bool {
+        match self.completed_splits.pop() {
+            Some((split, _)) => {
+                self.todo_splits.insert(0, split);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes every split todo again
+    pub fn reset(&mut self) {
+        let completed = self.completed_splits.drain(..).map(|(split, _)| split);
+        self.todo_splits = completed.chain(self.todo_splits.drain(..)).collect();
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Splits {
+    #[serde(deserialize_with = "SplitMode::deserialize_compat")]
+    pub split_mode: SplitMode,
+    // The default of the split mode when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing_method: Option<TimingMethod>,
+    // The default of the split mode when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_rule: Option<StartRule>,
+    // The defaults of the split mode when missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reset_rules: Option<Vec<ResetRule>>,
+    pub splits: Vec<Split>,
+}
+
+impl Splits {
+    /// The clock whose time counts for runs of these splits
+    pub fn timing_method(&self) -> TimingMethod {
+        self.timing_method
+            .unwrap_or_else(|| self.split_mode.default_timing_method())
+    }
+
+    /// When runs of these splits start by themselves
+    pub fn start_rule(&self) -> StartRule {
+        self.start_rule
+            .clone()
+            .unwrap_or_else(|| StartRule::default_for(self.split_mode))
+    }
+
+    /// When runs of these splits are reset by themselves
+    pub fn reset_rules(&self) -> Vec<ResetRule> {
+        self.reset_rules
+            .clone()
+            .unwrap_or_else(|| ResetRule::defaults_for(self.split_mode))
+    }
+
+    /// The in-game clock that is recorded along with the real time, which is the timing method
+    /// unless that is the real time
+    pub fn game_clock(&self) -> TimingMethod {
+        match self.timing_method() {
+            TimingMethod::RealTime => self.split_mode.default_timing_method(),
+            method => method,
+        }
+    }
+
+    /// Checks that every split can be accomplished in the declared [`SplitMode`]
+    pub fn validate(&self) -> Result<(), SplitsError> {
+        if let SplitMode::IndividualLevel { chapter, .. } = self.split_mode {
+            for (index, split) in self.splits.iter().enumerate() {
+                if split.chapter != chapter {
+                    return Err(SplitsError::WrongChapter {
+                        index,
+                        chapter: split.chapter,
+                        expected: chapter,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SplitsError {
+    /// A split of an individual level run is for another chapter
+    WrongChapter {
+        index: usize,
+        chapter: i32,
+        expected: i32,
+    },
+}
+
+impl fmt::Display for SplitsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SplitsError::WrongChapter {
+                index,
+                chapter,
+                expected,
+            } => write!(
+                f,
+                "split {} is for chapter {}, but the run is chapter {}",
+                index + 1,
+                chapter,
+                expected
+            ),
+        }
+    }
+}
+
+impl Error for SplitsError {}
@@ -0,0 +1,360 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Attempt, AttemptSplit, CurrentSplits, Split, TimingMethod};
+
+/// What a [`Timer`] is doing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimerState {
+    NotRunning,
+    Running,
+    Paused,
+    /// Every split is done, until the last one is undone or the timer is reset
+    Ended,
+}
+
+impl TimerState {
+    pub fn name(self) -> &'static str {
+        match self {
+            TimerState::NotRunning => "not_running",
+            TimerState::Running => "running",
+            TimerState::Paused => "paused",
+            TimerState::Ended => "ended",
+        }
+    }
+}
+
+impl fmt::Display for TimerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TimerState::NotRunning => "Not running",
+            TimerState::Running => "Running",
+            TimerState::Paused => "Paused",
+            TimerState::Ended => "Ended",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A reading of both clocks a run is timed on, or the time of a run on both, in milliseconds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Times {
+    /// The in-game time, of the chapter or of the file
+    pub game: u64,
+    /// The wall clock time
+    pub real: u64,
+}
+
+impl Times {
+    /// The time that counts for `method`
+    pub fn get(self, method: TimingMethod) -> u64 {
+        match method {
+            TimingMethod::ChapterTime | TimingMethod::FileTime => self.game,
+            TimingMethod::RealTime => self.real,
+        }
+    }
+
+    fn since(self, start: Times) -> Times {
+        Times {
+            game: self.game.saturating_sub(start.game),
+            real: self.real.saturating_sub(start.real),
+        }
+    }
+}
+
+/// A run through the splits, timed on both clocks of the [`Times`] passed to every operation.
+///
+/// Operations that do not apply in the current state do nothing and return false.
+#[derive(Debug)]
+pub struct Timer {
+    state: TimerState,
+    splits: CurrentSplits,
+    // The clock readings the run started at, moved forward by the time spent paused
+    start: Times,
+    // The time of the run while paused or ended
+    stopped_at: Times,
+}
+
+impl Timer {
+    pub fn new(splits: Vec<Split>) -> Self {
+        Timer {
+            state: TimerState::NotRunning,
+            splits: CurrentSplits::new(splits),
+            start: Times::default(),
+            stopped_at: Times::default(),
+        }
+    }
+
+    pub fn state(&self) -> TimerState {
+        self.state
+    }
+
+    /// The splits along with the time of the run at each completed one
+    pub fn splits(&self) -> &CurrentSplits {
+        &self.splits
+    }
+
+    /// The time of the run when the clocks read `now`
+    pub fn time(&self, now: Times) -> Times {
+        match self.state {
+            TimerState::NotRunning => Times::default(),
+            TimerState::Running => now.since(self.start),
+            TimerState::Paused | TimerState::Ended => self.stopped_at,
+        }
+    }
+
+    /// Starts the run from zero
+    pub fn start(&mut self, now: Times) -> bool {
+        if self.state != TimerState::NotRunning {
+            return false;
+        }
+        self.start = now;
+        self.state = if self.splits.todo_splits.is_empty() {
+            TimerState::Ended
+        } else {
+            TimerState::Running
+        };
+        true
+    }
+
+    /// Completes the current split at the current time, ending the run after the last one
+    pub fn split(&mut self, now: Times) -> bool {
+        if self.state != TimerState::Running {
+            return false;
+        }
+        let time = self.time(now);
+        self.splits.split(time);
+        if self.splits.todo_splits.is_empty() {
+            self.stopped_at = time;
+            self.state = TimerState::Ended;
+        }
+        true
+    }
+
+    /// Moves past the current split without a time.  The last split can only be split.
+    pub fn skip(&mut self) -> bool {
+        if self.state != TimerState::Running || self.splits.todo_splits.len() < 2 {
+            return false;
+        }
+        self.splits.skip()
+    }
+
+    /// Makes the last completed split current again, continuing an ended run as if it never
+    /// stopped
+    pub fn undo(&mut self) -> bool {
+        if self.state == TimerState::NotRunning || !self.splits.undo() {
+            return false;
+        }
+        if self.state == TimerState::Ended {
+            self.state = TimerState::Running;
+        }
+        true
+    }
+
+    /// Stops the time of the run until it is resumed
+    pub fn pause(&mut self, now: Times) -> bool {
+        if self.state != TimerState::Running {
+            return false;
+        }
+        self.stopped_at = self.time(now);
+        self.state = TimerState::Paused;
+        true
+    }
+
+    /// Continues the time of the run from where it was paused
+    pub fn resume(&mut self, now: Times) -> bool {
+        if self.state != TimerState::Paused {
+            return false;
+        }
+        self.start = now.since(self.stopped_at);
+        self.state = TimerState::Running;
+        true
+    }
+
+    /// The run so far when the clocks read `now`, or None before it started
+    pub fn attempt(&self, now: Times) -> Option<Attempt> {
+        if self.state == TimerState::NotRunning {
+            return None;
+        }
+        let splits = self
+            .splits
+            .completed_splits
+            .iter()
+            .map(|(split, time)| AttemptSplit {
+                name: split.display_short(),
+                time: *time,
+            });
+        Some(Attempt {
+            finished: self.state == TimerState::Ended,
+            time: self.time(now),
+            splits: splits.collect(),
+        })
+    }
+
+    /// Throws away the run, making every split todo again
+    pub fn reset(&mut self) -> bool {
+        if self.state == TimerState::NotRunning {
+            return false;
+        }
+        self.splits.reset();
+        self.state = TimerState::NotRunning;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SplitKind;
+
+    fn split(name: &str) -> Split {
+        Split {
+            name: Some(name.to_string()),
+            chapter: 1,
+            split_kind: SplitKind::ChapterComplete,
+            pb_time: None,
+        }
+    }
+
+    fn at(game: u64, real: u64) -> Times {
+        Times { game, real }
+    }
+
+    fn running(names: &[&str]) -> Timer {
+        let mut timer = Timer::new(names.iter().map(|name| split(name)).collect());
+        assert!(timer.start(at(100, 1_000)));
+        timer
+    }
+
+    #[test]
+    fn start_counts_from_the_given_clocks() {
+        let mut timer = Timer::new(vec![split("a")]);
+        assert_eq!(timer.state(), TimerState::NotRunning);
+        assert_eq!(timer.time(at(500, 5_000)), Times::default());
+
+        assert!(timer.start(at(100, 1_000)));
+        assert_eq!(timer.state(), TimerState::Running);
+        assert_eq!(timer.time(at(600, 1_700)), at(500, 700));
+        assert!(!timer.start(at(200, 2_000)));
+    }
+
+    #[test]
+    fn start_without_splits_ends_at_once() {
+        let mut timer = Timer::new(Vec::new());
+        assert!(timer.start(at(0, 0)));
+        assert_eq!(timer.state(), TimerState::Ended);
+    }
+
+    #[test]
+    fn split_records_times_and_ends_after_the_last() {
+        let mut timer = running(&["a", "b"]);
+        assert!(timer.split(at(300, 1_400)));
+        assert_eq!(timer.state(), TimerState::Running);
+        assert_eq!(timer.splits().completed_splits[0].1, Some(at(200, 400)));
+
+        assert!(timer.split(at(600, 2_000)));
+        assert_eq!(timer.state(), TimerState::Ended);
+        assert_eq!(timer.splits().completed_splits[1].1, Some(at(500, 1_000)));
+        // The time stays where the run ended
+        assert_eq!(timer.time(at(900, 9_000)), at(500, 1_000));
+        assert!(!timer.split(at(700, 3_000)));
+    }
+
+    #[test]
+    fn split_needs_a_running_timer() {
+        let mut timer = Timer::new(vec![split("a")]);
+        assert!(!timer.split(at(0, 0)));
+
+        let mut timer = running(&["a", "b"]);
+        assert!(timer.pause(at(200, 1_100)));
+        assert!(!timer.split(at(300, 1_200)));
+        assert!(timer.splits().completed_splits.is_empty());
+    }
+
+    #[test]
+    fn skip_moves_on_without_a_time_but_not_past_the_last_split() {
+        let mut timer = running(&["a", "b"]);
+        assert!(timer.skip());
+        assert_eq!(timer.splits().completed_splits[0].1, None);
+        assert_eq!(timer.splits().todo_splits.len(), 1);
+        assert!(!timer.skip());
+
+        assert!(!Timer::new(vec![split("a"), split("b")]).skip());
+    }
+
+    #[test]
+    fn undo_brings_back_the_last_split_and_continues_an_ended_run() {
+        let mut timer = running(&["a"]);
+        assert!(!timer.undo());
+        assert!(timer.split(at(300, 1_400)));
+        assert_eq!(timer.state(), TimerState::Ended);
+
+        assert!(timer.undo());
+        assert_eq!(timer.state(), TimerState::Running);
+        assert!(timer.splits().completed_splits.is_empty());
+        // Undoing continues the run as if it never stopped
+        assert_eq!(timer.time(at(400, 1_500)), at(300, 500));
+
+        assert!(!Timer::new(vec![split("a")]).undo());
+    }
+
+    #[test]
+    fn pause_stops_the_time_until_resumed() {
+        let mut timer = running(&["a"]);
+        assert!(!timer.resume(at(150, 1_050)));
+        assert!(timer.pause(at(200, 1_100)));
+        assert_eq!(timer.state(), TimerState::Paused);
+        assert!(!timer.pause(at(250, 1_150)));
+        assert_eq!(timer.time(at(700, 5_000)), at(100, 100));
+
+        assert!(timer.resume(at(700, 5_000)));
+        assert_eq!(timer.state(), TimerState::Running);
+        assert_eq!(timer.time(at(750, 5_050)), at(150, 150));
+        assert!(!timer.resume(at(800, 5_100)));
+    }
+
+    #[test]
+    fn attempt_keeps_the_run_so_far() {
+        assert!(Timer::new(vec![split("a")]).attempt(at(0, 0)).is_none());
+
+        let mut timer = running(&["a", "b", "c"]);
+        assert!(timer.skip());
+        assert!(timer.split(at(300, 1_400)));
+        let attempt = timer.attempt(at(400, 1_600)).unwrap();
+        assert!(!attempt.finished);
+        assert_eq!(attempt.time, at(300, 600));
+        let splits = attempt
+            .splits
+            .iter()
+            .map(|split| (split.name.as_str(), split.time))
+            .collect::<Vec<_>>();
+        assert_eq!(splits, [("a", None), ("b", Some(at(200, 400)))]);
+
+        assert!(timer.split(at(500, 2_000)));
+        assert!(timer.attempt(at(900, 9_000)).unwrap().finished);
+    }
+
+    #[test]
+    fn reset_makes_every_split_todo_again() {
+        assert!(!Timer::new(vec![split("a")]).reset());
+
+        let mut timer = running(&["a", "b"]);
+        assert!(timer.split(at(300, 1_400)));
+        assert!(timer.reset());
+        assert_eq!(timer.state(), TimerState::NotRunning);
+        assert!(timer.splits().completed_splits.is_empty());
+        assert_eq!(timer.splits().todo_splits.len(), 2);
+        assert_eq!(timer.time(at(900, 9_000)), Times::default());
+        assert!(!timer.reset());
+
+        // Paused and ended runs can be reset as well
+        let mut timer = running(&["a"]);
+        assert!(timer.pause(at(200, 1_100)));
+        assert!(timer.reset());
+        let mut timer = running(&["a"]);
+        assert!(timer.split(at(200, 1_100)));
+        assert!(timer.reset());
+        assert!(timer.start(at(300, 1_200)));
+    }
+}
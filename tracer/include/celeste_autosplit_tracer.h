@@ -0,0 +1,80 @@
+#ifndef CELESTE_AUTOSPLIT_TRACER_H
+#define CELESTE_AUTOSPLIT_TRACER_H
+
+/* Generated by cbindgen from src/capi.rs, do not edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Bumped whenever a function or [`CatDump`] changes incompatibly
+#define CAT_ABI_VERSION 1
+
+// [`cat_poll`] filled in the dump
+#define CAT_OK 0
+
+// The game was mid-update on every read, poll again
+#define CAT_INCONSISTENT 1
+
+// The game can not be read anymore, detach from it
+#define CAT_ERROR -1
+
+// A Celeste process being traced, created by [`cat_attach`] and freed by [`cat_detach`]
+typedef struct CatCeleste CatCeleste;
+
+// The state of the game, mirroring `Dump`.  Times are in milliseconds.
+typedef struct CatDump {
+  int32_t chapter;
+  int32_t mode;
+  bool timer_active;
+  bool chapter_started;
+  bool chapter_complete;
+  uint64_t chapter_time;
+  int32_t chapter_strawberries;
+  bool chapter_cassette;
+  bool chapter_heart;
+  uint64_t file_time;
+  int32_t file_strawberries;
+  int32_t file_cassettes;
+  int32_t file_hearts;
+  uint32_t chapter_checkpoints;
+  bool in_cutscene;
+  uint32_t death_count;
+  uint64_t generation;
+} CatDump;
+
+// Writes the pids of up to `capacity` running Celeste processes, newest first, to `pids`.
+// Returns how many were found, which may be more than `capacity`, or -1 on failure.
+//
+// # Safety
+// `pids` must be valid for writing `capacity` values, or null if `capacity` is 0.
+int32_t cat_find(uint32_t *pids, size_t capacity);
+
+// Attaches to the Celeste process `pid`, returning null on failure
+struct CatCeleste *cat_attach(uint32_t pid);
+
+// Reads the current state of the game into `out`, returning [`CAT_OK`], [`CAT_INCONSISTENT`]
+// or [`CAT_ERROR`].  `out` is only written on success.
+//
+// # Safety
+// `handle` must come from [`cat_attach`] and `out` must be valid for writing.
+int32_t cat_poll(struct CatCeleste *handle, struct CatDump *out);
+
+// Copies the room name of the latest successful [`cat_poll`] into `buf` as a nul terminated
+// string, truncating it to fit `len` bytes.  Returns the length of the whole name, like
+// `snprintf`, which is 0 before the first poll or outside of a level.
+//
+// # Safety
+// `handle` must come from [`cat_attach`] and `buf` must be valid for writing `len` bytes,
+// or null if `len` is 0.
+size_t cat_level_name(const struct CatCeleste *handle, char *buf, size_t len);
+
+// Detaches from the game and frees `handle`.  Does nothing if `handle` is null.
+//
+// # Safety
+// `handle` must come from [`cat_attach`] and must not be used afterwards.
+void cat_detach(struct CatCeleste *handle);
+
+#endif /* CELESTE_AUTOSPLIT_TRACER_H */
@@ -0,0 +1,42 @@
+//! Serves the memory of one Celeste process to unprivileged autosplitter processes.
+//! This is meant to be the only binary given `CAP_SYS_PTRACE`, e.g. with
+//! `sudo setcap cap_sys_ptrace=ep celeste-mem-helper`
+
+use std::{env, path::PathBuf, process};
+
+use celeste_autosplit_tracer as cat;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (pid, socket_path) = match (args.next(), args.next(), args.next()) {
+        (Some(pid), Some(socket_path), None) => match pid.parse::<u32>() {
+            Ok(pid) => (pid, PathBuf::from(socket_path)),
+            Err(_) => usage(),
+        },
+        (Some(socket_path), None, None) => {
+            // Without an explicit pid, trace the only running Celeste
+            match cat::find_celeste().as_deref() {
+                Ok([only]) => (only.pid, PathBuf::from(socket_path)),
+                Ok(_) => {
+                    eprintln!("Found multiple Celeste processes, please pass the pid to trace");
+                    process::exit(1);
+                }
+                Err(e) => {
+                    eprintln!("Unable to find Celeste: {:?}", e);
+                    process::exit(1);
+                }
+            }
+        }
+        _ => usage(),
+    };
+
+    if let Err(e) = cat::serve_helper(pid, &socket_path) {
+        eprintln!("Unable to serve on {}: {}", socket_path.display(), e);
+        process::exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: celeste-mem-helper [pid] <socket path>");
+    process::exit(2);
+}
@@ -0,0 +1,209 @@
+//! Inspects a running Celeste without the timer, for debugging the tracer and scripting.
+
+use std::{env, path::PathBuf, process, time::Duration};
+
+use celeste_autosplit_tracer as cat;
+
+const USAGE: &str = "\
+Usage: celeste-trace <command> [options]
+
+Commands:
+  find      list the running Celeste processes
+  dump      print the current state of the game once
+  stream    keep writing the state of the game
+  info      print versions and the addresses resolved while attaching
+  serve     stream the state of the game and its events to clients on a Unix socket
+
+Options:
+  --pid <pid>            the Celeste to trace, needed when several are running
+  --helper <socket>      read memory through a running celeste-mem-helper
+  --format <format>      stream as binary, shm, memfd, json or csv (default json)
+  --output <path>        where to stream to, stdout for json and csv if not given
+  --socket <path>        where to serve the state of the game
+  --interval <ms>        how often to poll while streaming or serving (default 12)
+  --changes              only stream json and csv lines when the state changed";
+
+#[derive(Default)]
+struct Options {
+    pid: Option<u32>,
+    helper: Option<PathBuf>,
+    format: Option<String>,
+    output: Option<PathBuf>,
+    socket: Option<PathBuf>,
+    interval: Option<u64>,
+    changes: bool,
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let command = args.next().unwrap_or_else(|| usage());
+
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--pid" => options.pid = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--helper" => options.helper = Some(PathBuf::from(value())),
+            "--format" => options.format = Some(value()),
+            "--output" => options.output = Some(PathBuf::from(value())),
+            "--socket" => options.socket = Some(PathBuf::from(value())),
+            "--interval" => options.interval = Some(value().parse().unwrap_or_else(|_| usage())),
+            "--changes" => options.changes = true,
+            _ => usage(),
+        }
+    }
+
+    match command.as_str() {
+        "find" => find(),
+        "dump" => dump(&options),
+        "stream" => stream(&options),
+        "info" => info(&options),
+        "serve" => serve(&options),
+        "-h" | "--help" => println!("{}", USAGE),
+        _ => usage(),
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn fail<E: std::fmt::Display>(context: &str, e: E) -> ! {
+    eprintln!("{}: {}", context, e);
+    process::exit(1);
+}
+
+fn find() {
+    match cat::find_celeste() {
+        Ok(candidates) => {
+            for candidate in candidates {
+                let exe = candidate.exe.as_ref().map_or_else(
+                    || "<unknown exe>".to_string(),
+                    |exe| exe.display().to_string(),
+                );
+                let uptime = candidate
+                    .start_time
+                    .and_then(|start| start.elapsed().ok())
+                    .map_or_else(|| "?".to_string(), |up| format!("{}s", up.as_secs()));
+                println!(
+                    "{}\t{}\tup {}\tmatched by {:?}",
+                    candidate.pid, exe, uptime, candidate.matched_by
+                );
+            }
+        }
+        Err(cat::PIDError::NotFound) => {
+            eprintln!("No running Celeste found");
+            process::exit(1);
+        }
+        Err(e) => fail("Unable to search for Celeste", format!("{:?}", e)),
+    }
+}
+
+/// Picks the pid to trace from the options, or the only running Celeste
+fn pid(options: &Options) -> u32 {
+    if let Some(pid) = options.pid {
+        return pid;
+    }
+
+    match cat::find_celeste().as_deref() {
+        Ok([only]) => only.pid,
+        Ok(_) => {
+            eprintln!("Found multiple Celeste processes, please pass one with --pid");
+            process::exit(1);
+        }
+        Err(e) => fail("Unable to find Celeste", format!("{:?}", e)),
+    }
+}
+
+fn attach(options: &Options) -> cat::Celeste {
+    let celeste = match &options.helper {
+        Some(socket) => {
+            let client = cat::HelperClient::connect(socket)
+                .unwrap_or_else(|e| fail("Unable to connect to helper", e));
+            cat::Celeste::with_source(Box::new(client))
+        }
+        None => cat::Celeste::new(pid(options)),
+    };
+    let celeste = celeste.unwrap_or_else(|e| fail("Unable to connect to Celeste", e));
+    eprintln!("Connected to {}", celeste.domain_name());
+    celeste
+}
+
+fn dump(options: &Options) {
+    let celeste = attach(options);
+    // Retry a few times in case every read was torn by the game updating
+    for _ in 0..10 {
+        match celeste.get_data() {
+            Ok(dump) => {
+                println!("{:#?}", dump);
+                println!("level name: {}", dump.level_name());
+                println!(
+                    "chapter time: {}ms, file time: {}ms",
+                    dump.autosplitter_info.chapter_time(),
+                    dump.autosplitter_info.file_time()
+                );
+                return;
+            }
+            Err(cat::TraceError::Inconsistent(_)) => continue,
+            Err(e) => fail("Unable to read game state", e),
+        }
+    }
+    fail("Unable to read game state", "the game kept changing");
+}
+
+fn stream(options: &Options) {
+    let format = options.format.as_deref().unwrap_or("json");
+    let output = match (format, &options.output) {
+        ("binary", Some(path)) => cat::DumpOutput::File(path.clone()),
+        ("shm", Some(path)) => cat::DumpOutput::SharedMemory(path.clone()),
+        ("binary", None) | ("shm", None) => {
+            fail("Unable to stream", format!("{} needs --output", format))
+        }
+        ("memfd", _) => cat::DumpOutput::Memfd,
+        ("json", path) | ("csv", path) => cat::DumpOutput::Lines {
+            format: if format == "json" {
+                cat::LineFormat::JsonLines
+            } else {
+                cat::LineFormat::Csv
+            },
+            path: path.clone(),
+            only_changes: options.changes,
+        },
+        _ => usage(),
+    };
+
+    let celeste = attach(options);
+    if let Err(e) = cat::dump_info_loop(&output, &celeste, interval(options)) {
+        fail("Stopped streaming", e);
+    }
+}
+
+fn info(options: &Options) {
+    let celeste = attach(options);
+
+    println!("celeste-trace {}", env!("CARGO_PKG_VERSION"));
+    println!("dump format version {}", cat::VERSION);
+    println!("domain {}", celeste.domain_name());
+    match celeste.game_version() {
+        Ok(version) => println!("game version {}", version),
+        Err(e) => println!("game version unknown ({})", e),
+    }
+
+    println!();
+    for (name, addr) in celeste.addresses() {
+        println!("{:<20}{:#014X}", name, addr);
+    }
+}
+
+fn serve(options: &Options) {
+    let socket = options.socket.as_ref().unwrap_or_else(|| usage());
+    let celeste = attach(options);
+    if let Err(e) = cat::serve_state(&celeste, socket, interval(options)) {
+        fail("Stopped serving", e);
+    }
+}
+
+fn interval(options: &Options) -> Duration {
+    Duration::from_millis(options.interval.unwrap_or(12))
+}
@@ -0,0 +1,182 @@
+//! A C API over the tracer, for tools that are not written in Rust.
+//!
+//! Build the shared library `libceleste_autosplit_tracer.so` with
+//! `cargo build -p celeste_autosplit_tracer --release --features capi`
+//! and include `include/celeste_autosplit_tracer.h`, which is generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/celeste_autosplit_tracer.h` in `tracer/`.
+//!
+//! Handles are not thread safe, use each one from a single thread at a time.
+
+use std::{
+    os::raw::c_char,
+    panic::{self, AssertUnwindSafe},
+    ptr, slice,
+};
+
+use crate::{find_celeste, Celeste, Dump, PIDError, TraceError};
+
+/// Bumped whenever a function or [`CatDump`] changes incompatibly
+pub const CAT_ABI_VERSION: u32 = 1;
+
+/// [`cat_poll`] filled in the dump
+pub const CAT_OK: i32 = 0;
+/// The game was mid-update on every read, poll again
+pub const CAT_INCONSISTENT: i32 = 1;
+/// The game can not be read anymore, detach from it
+pub const CAT_ERROR: i32 = -1;
+
+/// A Celeste process being traced, created by [`cat_attach`] and freed by [`cat_detach`]
+pub struct CatCeleste {
+    celeste: Celeste,
+    // The latest dump from `cat_poll`, for `cat_level_name`
+    last: Option<Dump>,
+}
+
+/// The state of the game, mirroring `Dump`.  Times are in milliseconds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CatDump {
+    pub chapter: i32,
+    pub mode: i32,
+    pub timer_active: bool,
+    pub chapter_started: bool,
+    pub chapter_complete: bool,
+    pub chapter_time: u64,
+    pub chapter_strawberries: i32,
+    pub chapter_cassette: bool,
+    pub chapter_heart: bool,
+    pub file_time: u64,
+    pub file_strawberries: i32,
+    pub file_cassettes: i32,
+    pub file_hearts: i32,
+    pub chapter_checkpoints: u32,
+    pub in_cutscene: bool,
+    pub death_count: u32,
+    pub generation: u64,
+}
+
+impl From<&Dump> for CatDump {
+    fn from(dump: &Dump) -> Self {
+        let info = &dump.autosplitter_info;
+        CatDump {
+            chapter: info.chapter,
+            mode: info.mode,
+            timer_active: info.timer_active,
+            chapter_started: info.chapter_started,
+            chapter_complete: info.chapter_complete,
+            chapter_time: info.chapter_time(),
+            chapter_strawberries: info.chapter_strawberries,
+            chapter_cassette: info.chapter_cassette,
+            chapter_heart: info.chapter_heart,
+            file_time: info.file_time(),
+            file_strawberries: info.file_strawberries,
+            file_cassettes: info.file_cassettes,
+            file_hearts: info.file_hearts,
+            chapter_checkpoints: dump.chapter_checkpoints,
+            in_cutscene: dump.in_cutscene,
+            death_count: dump.death_count,
+            generation: dump.generation,
+        }
+    }
+}
+
+/// Writes the pids of up to `capacity` running Celeste processes, newest first, to `pids`.
+/// Returns how many were found, which may be more than `capacity`, or -1 on failure.
+///
+/// # Safety
+/// `pids` must be valid for writing `capacity` values, or null if `capacity` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn cat_find(pids: *mut u32, capacity: usize) -> i32 {
+    let found = match panic::catch_unwind(find_celeste) {
+        Ok(Ok(found)) => found,
+        Ok(Err(PIDError::NotFound)) => Vec::new(),
+        _ => return -1,
+    };
+    if capacity > 0 {
+        // SAFETY: the caller guarantees `pids` holds `capacity` values
+        let pids = unsafe { slice::from_raw_parts_mut(pids, capacity) };
+        for (pid, process) in pids.iter_mut().zip(&found) {
+            *pid = process.pid;
+        }
+    }
+    found.len() as i32
+}
+
+/// Attaches to the Celeste process `pid`, returning null on failure
+#[no_mangle]
+pub extern "C" fn cat_attach(pid: u32) -> *mut CatCeleste {
+    match panic::catch_unwind(|| Celeste::new(pid)) {
+        Ok(Ok(celeste)) => Box::into_raw(Box::new(CatCeleste {
+            celeste,
+            last: None,
+        })),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Reads the current state of the game into `out`, returning [`CAT_OK`], [`CAT_INCONSISTENT`]
+/// or [`CAT_ERROR`].  `out` is only written on success.
+///
+/// # Safety
+/// `handle` must come from [`cat_attach`] and `out` must be valid for writing.
+#[no_mangle]
+pub unsafe extern "C" fn cat_poll(handle: *mut CatCeleste, out: *mut CatDump) -> i32 {
+    if handle.is_null() || out.is_null() {
+        return CAT_ERROR;
+    }
+    // SAFETY: the caller guarantees `handle` is live and not used elsewhere
+    let handle = unsafe { &mut *handle };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| handle.celeste.get_data()));
+    match result {
+        Ok(Ok(dump)) => {
+            // SAFETY: the caller guarantees `out` is valid for writing
+            unsafe { out.write(CatDump::from(&dump)) };
+            handle.last = Some(dump);
+            CAT_OK
+        }
+        Ok(Err(TraceError::Inconsistent(_))) => CAT_INCONSISTENT,
+        _ => CAT_ERROR,
+    }
+}
+
+/// Copies the room name of the latest successful [`cat_poll`] into `buf` as a nul terminated
+/// string, truncating it to fit `len` bytes.  Returns the length of the whole name, like
+/// `snprintf`, which is 0 before the first poll or outside of a level.
+///
+/// # Safety
+/// `handle` must come from [`cat_attach`] and `buf` must be valid for writing `len` bytes,
+/// or null if `len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn cat_level_name(
+    handle: *const CatCeleste,
+    buf: *mut c_char,
+    len: usize,
+) -> usize {
+    if handle.is_null() {
+        return 0;
+    }
+    // SAFETY: the caller guarantees `handle` is live
+    let handle = unsafe { &*handle };
+    let name = handle.last.as_ref().map_or("", Dump::level_name).as_bytes();
+    if len > 0 {
+        let copied = name.len().min(len - 1);
+        // SAFETY: the caller guarantees `buf` holds `len` bytes, and `copied` is below that
+        unsafe {
+            ptr::copy_nonoverlapping(name.as_ptr(), buf.cast::<u8>(), copied);
+            buf.add(copied).write(0);
+        }
+    }
+    name.len()
+}
+
+/// Detaches from the game and frees `handle`.  Does nothing if `handle` is null.
+///
+/// # Safety
+/// `handle` must come from [`cat_attach`] and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn cat_detach(handle: *mut CatCeleste) {
+    if !handle.is_null() {
+        // SAFETY: the caller guarantees `handle` came from `Box::into_raw` in `cat_attach`
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
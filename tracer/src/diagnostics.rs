@@ -0,0 +1,168 @@
+use std::{
+    fmt, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::discovery;
+
+const CAP_SYS_PTRACE: u32 = 19;
+
+/// Everything the kernel considers when deciding whether we may read `/proc/<pid>/mem`
+#[derive(Clone, Debug)]
+pub struct PermissionReport {
+    pub pid: u32,
+    // None if Yama is not enabled in this kernel
+    pub ptrace_scope: Option<u32>,
+    // Whether we started the target (directly or through a wrapper)
+    pub is_ancestor: bool,
+    pub our_uid: u32,
+    // The real, effective and saved uids of the target, None if the process vanished
+    pub target_uids: Option<[u32; 3]>,
+    pub has_cap_sys_ptrace: bool,
+    // None if it could not be determined
+    pub target_dumpable: Option<bool>,
+    // The path of this executable, for suggesting `setcap`
+    pub own_exe: Option<PathBuf>,
+}
+
+/// Works out why access to the memory of `pid` could be denied
+pub fn diagnose_permissions(pid: u32) -> PermissionReport {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+    let target_uids = status_field(&proc_dir, "Uid").and_then(|uids| {
+        let mut uids = uids.split_whitespace().map(|uid| uid.parse::<u32>().ok());
+        Some([uids.next()??, uids.next()??, uids.next()??])
+    });
+
+    // Non-dumpable processes have their /proc entries owned by root regardless of who runs them
+    let target_dumpable = match (fs::metadata(&proc_dir), target_uids) {
+        (Ok(meta), Some([_, euid, _])) => Some(meta.uid() == euid),
+        _ => None,
+    };
+
+    let has_cap_sys_ptrace = status_field(Path::new("/proc/self"), "CapEff")
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .is_some_and(|caps| caps & (1 << CAP_SYS_PTRACE) != 0);
+
+    PermissionReport {
+        pid,
+        ptrace_scope: fs::read_to_string("/proc/sys/kernel/yama/ptrace_scope")
+            .ok()
+            .and_then(|scope| scope.trim().parse().ok()),
+        is_ancestor: discovery::is_descendant(pid, process::id()),
+        // SAFETY: geteuid has no preconditions and cannot fail
+        our_uid: unsafe { libc::geteuid() },
+        target_uids,
+        has_cap_sys_ptrace,
+        target_dumpable,
+        own_exe: std::env::current_exe().ok(),
+    }
+}
+
+impl PermissionReport {
+    pub fn same_user(&self) -> bool {
+        self.target_uids
+            .is_some_and(|uids| uids.iter().all(|&uid| uid == self.our_uid))
+    }
+
+    /// A concrete suggestion for getting access, based on the first check that fails
+    pub fn remedy(&self) -> String {
+        let setcap = match &self.own_exe {
+            Some(exe) => format!("sudo setcap cap_sys_ptrace=ep {}", exe.display()),
+            None => "sudo setcap cap_sys_ptrace=ep <path to the autosplitter>".to_string(),
+        };
+
+        if self.target_uids.is_none() {
+            return format!(
+                "Process {} no longer exists, restart Celeste and try again",
+                self.pid
+            );
+        }
+
+        if self.ptrace_scope == Some(3) {
+            return "ptrace is disabled system wide (ptrace_scope is 3) and can only be \
+                re-enabled by rebooting"
+                .to_string();
+        }
+
+        if self.has_cap_sys_ptrace {
+            return "We already have CAP_SYS_PTRACE, so the access was most likely denied by a \
+                security module such as SELinux or AppArmor"
+                .to_string();
+        }
+
+        if !self.same_user() {
+            return format!(
+                "Celeste is running as a different user, run the autosplitter as that user or \
+                grant it CAP_SYS_PTRACE with `{}`",
+                setcap
+            );
+        }
+
+        if self.target_dumpable == Some(false) {
+            return format!(
+                "Celeste is not dumpable, so only a process with CAP_SYS_PTRACE can read it. \
+                Grant it with `{}`",
+                setcap
+            );
+        }
+
+        match self.ptrace_scope {
+            Some(2) => format!(
+                "ptrace_scope is 2, so only processes with CAP_SYS_PTRACE can read other \
+                processes. Grant it with `{}`",
+                setcap
+            ),
+            Some(1) if !self.is_ancestor => format!(
+                "ptrace_scope is 1, so only the process that started Celeste can read it. \
+                Launch Celeste through the autosplitter with `--celeste <path to Celeste>`, \
+                grant CAP_SYS_PTRACE with `{}`, or allow it for the current boot with \
+                `sudo sysctl kernel.yama.ptrace_scope=0`",
+                setcap
+            ),
+            _ => "No obvious cause was found, check the kernel log for security module denials"
+                .to_string(),
+        }
+    }
+}
+
+impl fmt::Display for PermissionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn or_unknown<T: fmt::Display>(v: Option<T>) -> String {
+            v.map_or_else(|| "unknown".to_string(), |v| v.to_string())
+        }
+
+        writeln!(
+            f,
+            "Permission to access memory of process {} denied",
+            self.pid
+        )?;
+        writeln!(f, "  ptrace_scope:      {}", or_unknown(self.ptrace_scope))?;
+        writeln!(f, "  started by us:     {}", self.is_ancestor)?;
+        writeln!(f, "  our uid:           {}", self.our_uid)?;
+        writeln!(
+            f,
+            "  target uids:       {}",
+            or_unknown(
+                self.target_uids
+                    .map(|[real, effective, saved]| format!("{} {} {}", real, effective, saved))
+            )
+        )?;
+        writeln!(f, "  CAP_SYS_PTRACE:    {}", self.has_cap_sys_ptrace)?;
+        writeln!(
+            f,
+            "  target dumpable:   {}",
+            or_unknown(self.target_dumpable)
+        )?;
+        write!(f, "{}", self.remedy())
+    }
+}
+
+fn status_field(proc_dir: &Path, name: &str) -> Option<String> {
+    let status = fs::read_to_string(proc_dir.join("status")).ok()?;
+    status.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key == name).then(|| value.trim().to_string())
+    })
+}
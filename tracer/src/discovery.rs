@@ -0,0 +1,260 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+/// The name of the native launcher Celeste ships with on Linux
+const CELESTE_EXE: &str = "Celeste.bin.x86_64";
+/// `comm` is truncated to 15 bytes by the kernel, so the launcher shows up as this
+const CELESTE_COMM: &str = "Celeste.bin.x86";
+/// The managed assembly, present in the command line when the game is started through a system mono
+const CELESTE_ASSEMBLY: &str = "Celeste.exe";
+/// The names a system mono runs the assembly under
+const MONO: &str = "mono";
+const MONO_SGEN: &str = "mono-sgen";
+
+#[derive(Clone, Copy, Debug)]
+pub enum PIDError {
+    NotFound,
+    IOError,
+    TimedOut,
+}
+
+/// How a process was recognized as Celeste
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    Exe,
+    Comm,
+    Cmdline,
+}
+
+/// A running process that looks like Celeste
+#[derive(Clone, Debug)]
+pub struct CelesteProcess {
+    pub pid: u32,
+    // None if the exe link could not be read (usually because it belongs to another user)
+    pub exe: Option<PathBuf>,
+    // None if the start time could not be determined
+    pub start_time: Option<SystemTime>,
+    pub matched_by: MatchKind,
+}
+
+/// Scans `/proc` for every process that looks like Celeste, newest first.
+/// Processes that exit while the scan is in progress are skipped.
+pub fn find_celeste() -> Result<Vec<CelesteProcess>, PIDError> {
+    let entries = fs::read_dir(Path::new("/proc/")).map_err(|_| PIDError::IOError)?;
+    let own_pid = process::id();
+    let boot_time = boot_time();
+
+    let mut found = Vec::new();
+    for entry in entries.flatten() {
+        let pid = match entry
+            .file_name()
+            .to_str()
+            .and_then(|n| n.parse::<u32>().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        if pid == own_pid {
+            continue;
+        }
+
+        if let Some(candidate) = inspect_process(pid, boot_time) {
+            found.push(candidate);
+        }
+    }
+
+    if found.is_empty() {
+        return Err(PIDError::NotFound);
+    }
+
+    found.sort_by(|a, b| b.start_time.cmp(&a.start_time).then(b.pid.cmp(&a.pid)));
+    Ok(found)
+}
+
+/// Blocks until at least one Celeste process exists, polling `/proc` periodically.
+/// Waits forever if `timeout` is `None`.
+pub fn wait_for_celeste(timeout: Option<Duration>) -> Result<Vec<CelesteProcess>, PIDError> {
+    let start = Instant::now();
+    loop {
+        match find_celeste() {
+            Err(PIDError::NotFound) => {}
+            res => return res,
+        }
+
+        if let Some(timeout) = timeout {
+            if start.elapsed() >= timeout {
+                return Err(PIDError::TimedOut);
+            }
+        }
+
+        thread::sleep(Duration::from_millis(250));
+    }
+}
+
+fn inspect_process(pid: u32, boot_time: Option<SystemTime>) -> Option<CelesteProcess> {
+    let proc_dir = PathBuf::from(format!("/proc/{}", pid));
+
+    // The exe link is unreadable for processes owned by other users, so fall back to comm and cmdline
+    let exe = fs::read_link(proc_dir.join("exe")).ok();
+    let matched_by = if exe
+        .as_ref()
+        .is_some_and(|exe| exe.to_string_lossy().contains(CELESTE_EXE))
+    {
+        MatchKind::Exe
+    } else if read_trimmed(&proc_dir.join("comm")).ok()? == CELESTE_COMM {
+        MatchKind::Comm
+    } else if cmdline_matches(&fs::read(proc_dir.join("cmdline")).ok()?) {
+        MatchKind::Cmdline
+    } else {
+        return None;
+    };
+
+    let start_time = boot_time.and_then(|boot| {
+        let ticks = start_ticks(&proc_dir).ok()?;
+        Some(boot + Duration::from_millis(ticks * 1000 / clock_ticks_per_sec()))
+    });
+
+    Some(CelesteProcess {
+        pid,
+        exe,
+        start_time,
+        matched_by,
+    })
+}
+
+/// Returns whether `pid` is a process that [`find_celeste`] would report and whose real,
+/// effective and saved uids are all ours
+pub(crate) fn is_own_celeste(pid: u32) -> bool {
+    let status = match fs::read_to_string(format!("/proc/{}/status", pid)) {
+        Ok(status) => status,
+        Err(_) => return false,
+    };
+    let uids: Vec<libc::uid_t> = match status.lines().find_map(|line| line.strip_prefix("Uid:")) {
+        Some(uids) => uids
+            .split_whitespace()
+            .filter_map(|uid| uid.parse().ok())
+            .collect(),
+        None => return false,
+    };
+
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+    uids.len() >= 3 && uids[..3].iter().all(|&id| id == uid) && inspect_process(pid, None).is_some()
+}
+
+/// Whether the command line starts Celeste, either as the game itself or as the assembly
+/// passed to mono.  Later arguments are not looked at, as they may merely mention the game.
+fn cmdline_matches(cmdline: &[u8]) -> bool {
+    let file_name = |arg: &[u8]| {
+        let arg = String::from_utf8_lossy(arg);
+        Path::new(arg.as_ref())
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let mut args = cmdline.split(|&b| b == 0).map(file_name);
+    match args.next().as_deref() {
+        Some(CELESTE_EXE) | Some(CELESTE_ASSEMBLY) => true,
+        Some(MONO) | Some(MONO_SGEN) => {
+            matches!(
+                args.next().as_deref(),
+                Some(CELESTE_EXE) | Some(CELESTE_ASSEMBLY)
+            )
+        }
+        _ => false,
+    }
+}
+
+fn read_trimmed(path: &Path) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim_end().to_string())
+}
+
+/// Returns whether `pid` is `ancestor` or one of its (transitive) children
+pub(crate) fn is_descendant(pid: u32, ancestor: u32) -> bool {
+    let mut current = pid;
+    // pid 1 is the root of every process tree, so stop there
+    while current > 1 {
+        if current == ancestor {
+            return true;
+        }
+
+        current = match stat_field(Path::new(&format!("/proc/{}", current)), 4) {
+            Ok(parent) => parent,
+            Err(_) => return false,
+        };
+    }
+
+    current == ancestor
+}
+
+/// Reads the process start time in clock ticks since boot (field 22 of `/proc/<pid>/stat`)
+fn start_ticks(proc_dir: &Path) -> io::Result<u64> {
+    stat_field(proc_dir, 22)
+}
+
+/// Reads a numeric field of `/proc/<pid>/stat`, numbered from 1 like in `proc(5)`
+fn stat_field<T: std::str::FromStr>(proc_dir: &Path, field: usize) -> io::Result<T> {
+    let stat = fs::read_to_string(proc_dir.join("stat"))?;
+    // The command name may contain spaces and parentheses, so skip past the last `)`
+    let after_comm = stat
+        .rfind(')')
+        .map(|idx| &stat[idx + 1..])
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed stat"))?;
+
+    // The first field after comm is field 3 (state)
+    after_comm
+        .split_whitespace()
+        .nth(field - 3)
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed stat"))
+}
+
+fn boot_time() -> Option<SystemTime> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let secs = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+fn clock_ticks_per_sec() -> u64 {
+    // SAFETY: sysconf has no preconditions
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_game() {
+        assert!(cmdline_matches(b"/games/Celeste/Celeste.bin.x86_64\0"));
+        assert!(cmdline_matches(b"./Celeste.exe\0--fullscreen\0"));
+        assert!(cmdline_matches(
+            b"/usr/bin/mono\0/games/Celeste/Celeste.exe\0"
+        ));
+        assert!(cmdline_matches(b"mono-sgen\0Celeste.exe\0"));
+    }
+
+    #[test]
+    fn ignores_processes_mentioning_the_game() {
+        assert!(!cmdline_matches(b""));
+        assert!(!cmdline_matches(b"vim\0Celeste.exe\0"));
+        assert!(!cmdline_matches(b"/usr/bin/mono\0Other.exe\0Celeste.exe\0"));
+        assert!(!cmdline_matches(
+            b"gdb\0/games/Celeste/Celeste.bin.x86_64\0"
+        ));
+        assert!(!cmdline_matches(b"/games/Celeste/Celeste.exe.bak\0"));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::{format::json_string, Dump};
+
+/// Something that happened in the game between two dumps
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum GameEvent {
+    ChapterStarted { chapter: i32, mode: i32 },
+    ChapterComplete { chapter: i32, mode: i32 },
+    RoomChanged { from: String, to: String },
+    // The total number of deaths on the save file after dying
+    Death { deaths: u32 },
+    Strawberry { chapter_strawberries: i32 },
+    Cassette,
+    Heart,
+    Checkpoint { checkpoints: u32 },
+    CutsceneStarted,
+    CutsceneEnded,
+}
+
+impl GameEvent {
+    /// The names returned by [`GameEvent::kind`]
+    pub const KINDS: &'static [&'static str] = &[
+        "chapter_started",
+        "chapter_complete",
+        "room_changed",
+        "death",
+        "strawberry",
+        "cassette",
+        "heart",
+        "checkpoint",
+        "cutscene_started",
+        "cutscene_ended",
+    ];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GameEvent::ChapterStarted { .. } => "chapter_started",
+            GameEvent::ChapterComplete { .. } => "chapter_complete",
+            GameEvent::RoomChanged { .. } => "room_changed",
+            GameEvent::Death { .. } => "death",
+            GameEvent::Strawberry { .. } => "strawberry",
+            GameEvent::Cassette => "cassette",
+            GameEvent::Heart => "heart",
+            GameEvent::Checkpoint { .. } => "checkpoint",
+            GameEvent::CutsceneStarted => "cutscene_started",
+            GameEvent::CutsceneEnded => "cutscene_ended",
+        }
+    }
+
+    /// Works out what happened from `prev` to `next`, in the order the checks are listed in
+    /// [`GameEvent::KINDS`]
+    pub fn between(prev: &Dump, next: &Dump) -> Vec<GameEvent> {
+        let (before, after) = (&prev.autosplitter_info, &next.autosplitter_info);
+        let mut events = Vec::new();
+
+        if after.chapter_started && !before.chapter_started {
+            events.push(GameEvent::ChapterStarted {
+                chapter: after.chapter,
+                mode: after.mode,
+            });
+        }
+        if after.chapter_complete && !before.chapter_complete {
+            events.push(GameEvent::ChapterComplete {
+                chapter: after.chapter,
+                mode: after.mode,
+            });
+        }
+        if prev.level_name() != next.level_name() && !next.level_name().is_empty() {
+            events.push(GameEvent::RoomChanged {
+                from: prev.level_name().to_string(),
+                to: next.level_name().to_string(),
+            });
+        }
+        if next.death_count > prev.death_count {
+            events.push(GameEvent::Death {
+                deaths: next.death_count,
+            });
+        }
+        if after.chapter_strawberries > before.chapter_strawberries {
+            events.push(GameEvent::Strawberry {
+                chapter_strawberries: after.chapter_strawberries,
+            });
+        }
+        if after.chapter_cassette && !before.chapter_cassette {
+            events.push(GameEvent::Cassette);
+        }
+        if after.chapter_heart && !before.chapter_heart {
+            events.push(GameEvent::Heart);
+        }
+        if next.chapter_checkpoints > prev.chapter_checkpoints {
+            events.push(GameEvent::Checkpoint {
+                checkpoints: next.chapter_checkpoints,
+            });
+        }
+        if next.in_cutscene != prev.in_cutscene {
+            events.push(if next.in_cutscene {
+                GameEvent::CutsceneStarted
+            } else {
+                GameEvent::CutsceneEnded
+            });
+        }
+
+        events
+    }
+
+    /// Formats the event as a JSON object with its kind and data
+    pub fn to_json(&self) -> String {
+        let data = match self {
+            GameEvent::ChapterStarted { chapter, mode }
+            | GameEvent::ChapterComplete { chapter, mode } => {
+                format!(",\"chapter\":{},\"mode\":{}", chapter, mode)
+            }
+            GameEvent::RoomChanged { from, to } => {
+                format!(",\"from\":{},\"to\":{}", json_string(from), json_string(to))
+            }
+            GameEvent::Death { deaths } => format!(",\"deaths\":{}", deaths),
+            GameEvent::Strawberry {
+                chapter_strawberries,
+            } => format!(",\"chapter_strawberries\":{}", chapter_strawberries),
+            GameEvent::Checkpoint { checkpoints } => format!(",\"checkpoints\":{}", checkpoints),
+            GameEvent::Cassette
+            | GameEvent::Heart
+            | GameEvent::CutsceneStarted
+            | GameEvent::CutsceneEnded => String::new(),
+        };
+        format!("{{\"kind\":\"{}\"{}}}", self.kind(), data)
+    }
+}
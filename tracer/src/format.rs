@@ -0,0 +1,586 @@
+//! The binary format [`dump_info_loop`](crate::dump_info_loop) writes, and a reader for it.
+//!
+//! Everything is little endian.  A file holds a single dump laid out as
+//!
+//! | size | contents                                                              |
+//! |------|-----------------------------------------------------------------------|
+//! | 4    | the magic `CATD`                                                      |
+//! | 2    | the format version, currently 1                                       |
+//! | 2    | the number of fields                                                  |
+//! | 4    | the length of the whole dump in bytes, including this header          |
+//! | ...  | the field table, per field a `u8` type, a `u8` name length and the name |
+//! | ...  | the values of the fields, in the order of the table                   |
+//!
+//! The types are
+//! - `0` bool, a byte that is `0` or `1`
+//! - `1` i32
+//! - `2` u32
+//! - `3` u64
+//! - `4` string, a `u32` byte length followed by UTF-8
+//!
+//! Readers should look fields up by name and skip the ones they do not know, since new
+//! fields can be added without changing the version.  The version only changes when an
+//! existing field changes its meaning or type, or when the layout above changes.
+//!
+//! The fields of version 1 are the public fields of [`AutosplitterInfo`](crate::AutosplitterInfo) and [`Dump`] with
+//! the same names and types, except that `chapter_time` and `file_time` are in the game's
+//! ticks of 100ns, and `level_name` holds the name of the current room.
+//!
+//! [`Dump::to_json_line`] and [`Dump::to_csv_record`] write the same fields as text for
+//! tools that prefer it.
+//!
+//! The file is overwritten in place, so a reader can see a partially written dump.  Such
+//! reads fail with [`FormatError::Truncated`] or decode a mix of two dumps, and should be
+//! retried when `generation` does not match a second read.
+
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    error::Error,
+    fmt::{self, Write},
+    fs, io,
+    path::Path,
+};
+
+use crate::Dump;
+
+pub const MAGIC: [u8; 4] = *b"CATD";
+pub const VERSION: u16 = 1;
+
+const HEADER_SIZE: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldType {
+    Bool,
+    I32,
+    U32,
+    U64,
+    Str,
+}
+
+impl FieldType {
+    fn from_u8(ty: u8) -> Option<Self> {
+        match ty {
+            0 => Some(FieldType::Bool),
+            1 => Some(FieldType::I32),
+            2 => Some(FieldType::U32),
+            3 => Some(FieldType::U64),
+            4 => Some(FieldType::Str),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            FieldType::Bool => 0,
+            FieldType::I32 => 1,
+            FieldType::U32 => 2,
+            FieldType::U64 => 3,
+            FieldType::Str => 4,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormatError {
+    Io(io::Error),
+    /// The data does not start with [`MAGIC`]
+    Magic([u8; 4]),
+    /// The data was written in a version this reader does not understand
+    Version(u16),
+    /// The data ended before the length in its header or its fields said it would
+    Truncated,
+    /// The field table holds a type this reader does not know, so the values can not be skipped
+    UnknownType(String, u8),
+    /// A known field has a different type than expected
+    WrongType {
+        field: String,
+        expected: FieldType,
+        found: FieldType,
+    },
+    /// A bool field is neither 0 nor 1
+    InvalidBool(String, u8),
+    /// A field name or string value is not valid UTF-8
+    InvalidUtf8,
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Io(e) => write!(f, "Unable to read dump: {}", e),
+            FormatError::Magic(magic) => write!(f, "Not a dump, found magic {:02X?}", magic),
+            FormatError::Version(version) => {
+                write!(f, "Unsupported dump version {}", version)
+            }
+            FormatError::Truncated => write!(f, "Dump is truncated"),
+            FormatError::UnknownType(field, ty) => {
+                write!(f, "Field {} has unknown type {}", field, ty)
+            }
+            FormatError::WrongType {
+                field,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Field {} has type {:?} instead of {:?}",
+                field, found, expected
+            ),
+            FormatError::InvalidBool(field, value) => {
+                write!(f, "Field {} has invalid bool value {}", field, value)
+            }
+            FormatError::InvalidUtf8 => write!(f, "Dump contains invalid UTF-8"),
+        }
+    }
+}
+
+impl Error for FormatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormatError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FormatError {
+    fn from(e: io::Error) -> Self {
+        FormatError::Io(e)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    U64(u64),
+    Str(String),
+}
+
+impl Value {
+    fn field_type(&self) -> FieldType {
+        match self {
+            Value::Bool(_) => FieldType::Bool,
+            Value::I32(_) => FieldType::I32,
+            Value::U32(_) => FieldType::U32,
+            Value::U64(_) => FieldType::U64,
+            Value::Str(_) => FieldType::Str,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Bool(v) => out.push(*v as u8),
+            Value::I32(v) => out.extend(v.to_le_bytes()),
+            Value::U32(v) => out.extend(v.to_le_bytes()),
+            Value::U64(v) => out.extend(v.to_le_bytes()),
+            Value::Str(v) => {
+                out.extend((v.len() as u32).to_le_bytes());
+                out.extend(v.as_bytes());
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    /// Formats the value as JSON
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::I32(v) => write!(f, "{}", v),
+            Value::U32(v) => write!(f, "{}", v),
+            Value::U64(v) => write!(f, "{}", v),
+            Value::Str(v) => f.write_str(&json_string(v)),
+        }
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn fields(dump: &Dump) -> Vec<(&'static str, Value)> {
+    let asi = &dump.autosplitter_info;
+    vec![
+        ("chapter", Value::I32(asi.chapter)),
+        ("mode", Value::I32(asi.mode)),
+        ("timer_active", Value::Bool(asi.timer_active)),
+        ("chapter_started", Value::Bool(asi.chapter_started)),
+        ("chapter_complete", Value::Bool(asi.chapter_complete)),
+        ("chapter_time", Value::U64(asi.chapter_time)),
+        ("chapter_strawberries", Value::I32(asi.chapter_strawberries)),
+        ("chapter_cassette", Value::Bool(asi.chapter_cassette)),
+        ("chapter_heart", Value::Bool(asi.chapter_heart)),
+        ("file_time", Value::U64(asi.file_time)),
+        ("file_strawberries", Value::I32(asi.file_strawberries)),
+        ("file_cassettes", Value::I32(asi.file_cassettes)),
+        ("file_hearts", Value::I32(asi.file_hearts)),
+        ("chapter_checkpoints", Value::U32(dump.chapter_checkpoints)),
+        ("in_cutscene", Value::Bool(dump.in_cutscene)),
+        ("death_count", Value::U32(dump.death_count)),
+        ("generation", Value::U64(dump.generation)),
+        ("level_name", Value::Str(dump.level_name.clone())),
+    ]
+}
+
+fn encode_fields(fields: &[(&str, Value)]) -> Vec<u8> {
+    let mut table = Vec::new();
+    let mut values = Vec::new();
+    for (name, value) in fields.iter() {
+        table.push(value.field_type().to_u8());
+        table.push(name.len() as u8);
+        table.extend(name.as_bytes());
+        value.encode(&mut values);
+    }
+
+    let len = HEADER_SIZE + table.len() + values.len();
+    let mut data = Vec::with_capacity(len);
+    data.extend(MAGIC);
+    data.extend(VERSION.to_le_bytes());
+    data.extend((fields.len() as u16).to_le_bytes());
+    data.extend((len as u32).to_le_bytes());
+    data.extend(table);
+    data.extend(values);
+    data
+}
+
+impl Dump {
+    /// Encodes the dump in the versioned format described at the top of `format.rs`
+    pub fn encode(&self) -> Vec<u8> {
+        encode_fields(&fields(self))
+    }
+
+    /// Decodes a dump written by [`Dump::encode`], ignoring unknown fields and leaving
+    /// missing ones at their default.  The level pointer of the `AutosplitterInfo` is not
+    /// part of the format and is always 0.
+    pub fn decode(data: &[u8]) -> Result<Self, FormatError> {
+        let mut reader = Reader { data };
+
+        let magic: [u8; 4] = reader.take(4)?.try_into().unwrap();
+        if magic != MAGIC {
+            return Err(FormatError::Magic(magic));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(FormatError::Version(version));
+        }
+        let num_fields = reader.u16()?;
+        let len = reader.u32()? as usize;
+        // Anything after the dump is left over from a longer one written earlier
+        let body = data.get(HEADER_SIZE..len).ok_or(FormatError::Truncated)?;
+        let mut reader = Reader { data: body };
+
+        let mut table = Vec::with_capacity(num_fields as usize);
+        for _ in 0..num_fields {
+            let ty = reader.u8()?;
+            let name_len = reader.u8()? as usize;
+            let name = std::str::from_utf8(reader.take(name_len)?)
+                .map_err(|_| FormatError::InvalidUtf8)?
+                .to_string();
+            let ty =
+                FieldType::from_u8(ty).ok_or_else(|| FormatError::UnknownType(name.clone(), ty))?;
+            table.push((name, ty));
+        }
+
+        let mut values = HashMap::with_capacity(table.len());
+        for (name, ty) in table {
+            let value = match ty {
+                FieldType::Bool => match reader.u8()? {
+                    0 => Value::Bool(false),
+                    1 => Value::Bool(true),
+                    value => return Err(FormatError::InvalidBool(name, value)),
+                },
+                FieldType::I32 => Value::I32(reader.u32()? as i32),
+                FieldType::U32 => Value::U32(reader.u32()?),
+                FieldType::U64 => Value::U64(reader.u64()?),
+                FieldType::Str => {
+                    let len = reader.u32()? as usize;
+                    let s = std::str::from_utf8(reader.take(len)?)
+                        .map_err(|_| FormatError::InvalidUtf8)?;
+                    Value::Str(s.to_string())
+                }
+            };
+            values.insert(name, value);
+        }
+
+        let mut dump = Dump::default();
+        for (name, default) in fields(&dump) {
+            let value = match values.remove(name) {
+                Some(value) if value.field_type() == default.field_type() => value,
+                Some(value) => {
+                    return Err(FormatError::WrongType {
+                        field: name.to_string(),
+                        expected: default.field_type(),
+                        found: value.field_type(),
+                    })
+                }
+                None => continue,
+            };
+
+            let asi = &mut dump.autosplitter_info;
+            match (name, value) {
+                ("chapter", Value::I32(v)) => asi.chapter = v,
+                ("mode", Value::I32(v)) => asi.mode = v,
+                ("timer_active", Value::Bool(v)) => asi.timer_active = v,
+                ("chapter_started", Value::Bool(v)) => asi.chapter_started = v,
+                ("chapter_complete", Value::Bool(v)) => asi.chapter_complete = v,
+                ("chapter_time", Value::U64(v)) => asi.chapter_time = v,
+                ("chapter_strawberries", Value::I32(v)) => asi.chapter_strawberries = v,
+                ("chapter_cassette", Value::Bool(v)) => asi.chapter_cassette = v,
+                ("chapter_heart", Value::Bool(v)) => asi.chapter_heart = v,
+                ("file_time", Value::U64(v)) => asi.file_time = v,
+                ("file_strawberries", Value::I32(v)) => asi.file_strawberries = v,
+                ("file_cassettes", Value::I32(v)) => asi.file_cassettes = v,
+                ("file_hearts", Value::I32(v)) => asi.file_hearts = v,
+                ("chapter_checkpoints", Value::U32(v)) => dump.chapter_checkpoints = v,
+                ("in_cutscene", Value::Bool(v)) => dump.in_cutscene = v,
+                ("death_count", Value::U32(v)) => dump.death_count = v,
+                ("generation", Value::U64(v)) => dump.generation = v,
+                ("level_name", Value::Str(v)) => dump.level_name = v,
+                (name, _) => unreachable!("field {} is not decoded", name),
+            }
+        }
+
+        Ok(dump)
+    }
+
+    /// Formats the dump as a single line JSON object, without the trailing newline
+    pub fn to_json_line(&self) -> String {
+        let mut line = String::from("{");
+        for (idx, (name, value)) in fields(self).iter().enumerate() {
+            if idx != 0 {
+                line.push(',');
+            }
+            write!(line, "\"{}\":{}", name, value).unwrap();
+        }
+        line.push('}');
+        line
+    }
+
+    /// The CSV header matching [`Dump::to_csv_record`], without the trailing newline
+    pub fn csv_header() -> String {
+        fields(&Dump::default())
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Formats the dump as a CSV record, without the trailing newline
+    pub fn to_csv_record(&self) -> String {
+        fields(self)
+            .iter()
+            .map(|(_, value)| match value {
+                Value::Str(v) if v.contains(&[',', '"', '\n', '\r'][..]) => {
+                    format!("\"{}\"", v.replace('"', "\"\""))
+                }
+                Value::Str(v) => v.clone(),
+                value => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Reads a dump file written by [`dump_info_loop`](crate::dump_info_loop)
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self, FormatError> {
+        Self::decode(&fs::read(path)?)
+    }
+}
+
+/// Consumes little endian values from the front of a slice
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        if self.data.len() < len {
+            return Err(FormatError::Truncated);
+        }
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, FormatError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AutosplitterInfo;
+
+    fn sample() -> Dump {
+        let mut dump = Dump::default();
+        let asi = &mut dump.autosplitter_info;
+        asi.chapter = 7;
+        asi.mode = 1;
+        asi.timer_active = true;
+        asi.chapter_started = true;
+        asi.chapter_strawberries = 3;
+        asi.chapter_heart = true;
+        asi.set_chapter_time(61_250);
+        asi.set_file_time(3_600_000);
+        asi.file_strawberries = 120;
+        asi.file_hearts = -1;
+        dump.chapter_checkpoints = 2;
+        dump.death_count = 451;
+        dump.generation = 99;
+        dump.set_level_name("a-00 \"b\"");
+        dump
+    }
+
+    fn assert_same(decoded: &Dump, dump: &Dump) {
+        assert_eq!(decoded.autosplitter_info, dump.autosplitter_info);
+        assert_eq!(decoded.chapter_checkpoints, dump.chapter_checkpoints);
+        assert_eq!(decoded.in_cutscene, dump.in_cutscene);
+        assert_eq!(decoded.death_count, dump.death_count);
+        assert_eq!(decoded.generation, dump.generation);
+        assert_eq!(decoded.level_name(), dump.level_name());
+    }
+
+    #[test]
+    fn round_trip() {
+        let dump = sample();
+        assert_same(&Dump::decode(&dump.encode()).unwrap(), &dump);
+    }
+
+    #[test]
+    fn ignores_data_after_the_dump() {
+        let dump = sample();
+        let mut data = dump.encode();
+        data.extend(b"left over from a longer dump");
+        assert_same(&Dump::decode(&data).unwrap(), &dump);
+    }
+
+    #[test]
+    fn truncated() {
+        let data = sample().encode();
+        for len in [0, 3, HEADER_SIZE - 1, HEADER_SIZE, data.len() - 1] {
+            match Dump::decode(&data[..len]) {
+                Err(FormatError::Truncated) => {}
+                other => panic!("decoded {} bytes as {:?}", len, other),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut data = sample().encode();
+        data[..4].copy_from_slice(b"CATX");
+        match Dump::decode(&data) {
+            Err(FormatError::Magic(magic)) => assert_eq!(&magic, b"CATX"),
+            other => panic!("decoded bad magic as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        let dump = sample();
+        let mut fields = fields(&dump);
+        fields.insert(3, ("from_the_future", Value::Str("ignored".to_string())));
+        fields.push(("also_new", Value::U64(u64::MAX)));
+        assert_same(&Dump::decode(&encode_fields(&fields)).unwrap(), &dump);
+    }
+
+    #[test]
+    fn leaves_missing_fields_at_their_default() {
+        let data = encode_fields(&[("death_count", Value::U32(5))]);
+        let decoded = Dump::decode(&data).unwrap();
+        assert_eq!(decoded.death_count, 5);
+        assert_eq!(decoded.autosplitter_info, AutosplitterInfo::default());
+    }
+
+    #[test]
+    fn wrong_field_type() {
+        let data = encode_fields(&[("death_count", Value::U64(5))]);
+        match Dump::decode(&data) {
+            Err(FormatError::WrongType {
+                field,
+                expected: FieldType::U32,
+                found: FieldType::U64,
+            }) => assert_eq!(field, "death_count"),
+            other => panic!("decoded a u64 death count as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_field_type() {
+        let mut data = encode_fields(&[("death_count", Value::U32(5))]);
+        data[HEADER_SIZE] = 9;
+        match Dump::decode(&data) {
+            Err(FormatError::UnknownType(field, 9)) => assert_eq!(field, "death_count"),
+            other => panic!("decoded an unknown type as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn string_longer_than_the_dump() {
+        let mut data = encode_fields(&[("level_name", Value::Str("a-00".to_string()))]);
+        let len_at = data.len() - 8;
+        data[len_at..len_at + 4].copy_from_slice(&1000_u32.to_le_bytes());
+        assert!(matches!(Dump::decode(&data), Err(FormatError::Truncated)));
+    }
+
+    #[test]
+    fn invalid_bool() {
+        let mut data = encode_fields(&[("in_cutscene", Value::Bool(true))]);
+        *data.last_mut().unwrap() = 2;
+        match Dump::decode(&data) {
+            Err(FormatError::InvalidBool(field, 2)) => assert_eq!(field, "in_cutscene"),
+            other => panic!("decoded a bool of 2 as {:?}", other),
+        }
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_string("a-00"), "\"a-00\"");
+        assert_eq!(json_string("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(json_string("back\\slash"), "\"back\\\\slash\"");
+        assert_eq!(json_string("a\nb\rc\td"), "\"a\\nb\\rc\\td\"");
+        assert_eq!(
+            json_string("\u{1}\u{1f}\u{7f}"),
+            "\"\\u0001\\u001f\\u007f\""
+        );
+        assert_eq!(json_string("Céleste 山"), "\"Céleste 山\"");
+    }
+
+    #[test]
+    fn json_line_escapes_the_room_name() {
+        let mut dump = sample();
+        dump.set_level_name("\"rm\"\n\u{2}é");
+        let line = dump.to_json_line();
+        assert!(line.starts_with("{\"chapter\":7,\"mode\":1,\"timer_active\":true,"));
+        assert!(line.ends_with(",\"level_name\":\"\\\"rm\\\"\\n\\u0002é\"}"));
+        assert!(!line.contains('\n'));
+    }
+}
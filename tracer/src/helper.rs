@@ -0,0 +1,310 @@
+//! A small server that is the only part of the autosplitter needing `CAP_SYS_PTRACE`.
+//! It opens the memory of a single Celeste process, drops its capabilities, and then serves
+//! reads to unprivileged clients of the same user over a Unix socket.
+//!
+//! The protocol is little endian.  On connecting the server sends the traced pid as a `u32`.
+//! Every request starts with an operation byte:
+//! - `0` reads memory, followed by the address as a `u64` and the length as a `u32`.
+//!   Answered with the requested bytes.
+//! - `1` reads `/proc/<pid>/maps`.  Answered with its length as a `u32` and its contents.
+//!
+//! Every answer is preceded by a status byte, which is `0` for success or `1` followed by
+//! an `i32` errno instead of the answer.
+
+use std::{
+    convert::TryInto,
+    fs::{self, File},
+    io::{self, Read, Write},
+    mem,
+    os::unix::{
+        fs::{FileExt, FileTypeExt},
+        io::AsRawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::Arc,
+    thread,
+};
+
+use crate::{discovery::is_own_celeste, load_mem, MemorySource};
+
+/// Requests larger than this are rejected so a client cannot make the helper allocate freely
+const MAX_READ: usize = 1 << 20;
+
+const OP_READ: u8 = 0;
+const OP_MAPS: u8 = 1;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERR: u8 = 1;
+
+/// Reads memory through a helper started with [`serve_helper`]
+#[derive(Debug)]
+pub struct HelperClient {
+    stream: UnixStream,
+    pid: u32,
+}
+
+impl HelperClient {
+    pub fn connect<P: AsRef<Path>>(socket_path: P) -> io::Result<Self> {
+        let mut stream = UnixStream::connect(socket_path)?;
+        let mut pid = [0_u8; 4];
+        stream.read_exact(&mut pid)?;
+        Ok(Self {
+            stream,
+            pid: u32::from_le_bytes(pid),
+        })
+    }
+
+    /// The pid of the process the helper is reading
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+}
+
+impl HelperClient {
+    fn read_status(&mut self) -> io::Result<()> {
+        let mut status = [0_u8; 1];
+        self.stream.read_exact(&mut status)?;
+        match status[0] {
+            STATUS_OK => Ok(()),
+            STATUS_ERR => {
+                let mut errno = [0_u8; 4];
+                self.stream.read_exact(&mut errno)?;
+                Err(io::Error::from_raw_os_error(i32::from_le_bytes(errno)))
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("helper sent invalid status {}", other),
+            )),
+        }
+    }
+}
+
+impl MemorySource for HelperClient {
+    fn read_at(&mut self, addr: usize, buf: &mut [u8]) -> io::Result<()> {
+        let mut request = [0_u8; 13];
+        request[0] = OP_READ;
+        request[1..9].copy_from_slice(&(addr as u64).to_le_bytes());
+        request[9..].copy_from_slice(&(buf.len() as u32).to_le_bytes());
+        self.stream.write_all(&request)?;
+
+        self.read_status()?;
+        self.stream.read_exact(buf)
+    }
+
+    fn maps(&mut self) -> io::Result<String> {
+        self.stream.write_all(&[OP_MAPS])?;
+        self.read_status()?;
+
+        let mut len = [0_u8; 4];
+        self.stream.read_exact(&mut len)?;
+        let mut maps = vec![0_u8; u32::from_le_bytes(len) as usize];
+        self.stream.read_exact(&mut maps)?;
+        String::from_utf8(maps).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Opens the memory of `pid`, drops all capabilities, and serves reads on `socket_path` forever
+pub fn serve_helper<P: AsRef<Path>>(pid: u32, socket_path: P) -> io::Result<()> {
+    let socket_path = socket_path.as_ref();
+    // The capabilities would let us open any process, so only ever open our own Celeste
+    if !is_own_celeste(pid) {
+        return Err(not_own_celeste(pid));
+    }
+    // The kernel only checks access when the files are opened, so the capabilities are not needed after
+    let files = Arc::new(ProcessFiles {
+        mem: load_mem(pid).map_err(io::Error::other)?,
+        maps: File::open(format!("/proc/{}/maps", pid))?,
+    });
+    // The pid may have been reused by another process while the files were opened
+    if !is_own_celeste(pid) {
+        return Err(not_own_celeste(pid));
+    }
+    drop_capabilities()?;
+
+    let listener = bind_private(socket_path)?;
+
+    println!(
+        "Serving memory of process {} on {}",
+        pid,
+        socket_path.display()
+    );
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Unable to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        if !same_user(&stream) {
+            eprintln!("Rejected a connection from another user");
+            continue;
+        }
+
+        let files = Arc::clone(&files);
+        thread::spawn(move || {
+            if let Err(e) = serve_client(stream, pid, &files) {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    eprintln!("Client disconnected: {}", e);
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn not_own_celeste(pid: u32) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("process {} is not a Celeste process of this user", pid),
+    )
+}
+
+/// Binds a socket at `socket_path` that only our user can connect to, replacing a socket
+/// left behind by a previous run
+pub(crate) fn bind_private(socket_path: &Path) -> io::Result<UnixListener> {
+    // Clean up a socket left behind by a previous run, but never delete anything else
+    if let Ok(meta) = fs::symlink_metadata(socket_path) {
+        if meta.file_type().is_socket() {
+            fs::remove_file(socket_path)?;
+        }
+    }
+
+    // Create the socket accessible only by our user, without a window where others could connect
+    // SAFETY: umask has no preconditions
+    let old_umask = unsafe { libc::umask(0o177) };
+    let listener = UnixListener::bind(socket_path);
+    unsafe { libc::umask(old_umask) };
+    listener
+}
+
+/// The files of the traced process, opened while the helper still had its capabilities
+struct ProcessFiles {
+    mem: File,
+    maps: File,
+}
+
+fn serve_client(mut stream: UnixStream, pid: u32, files: &ProcessFiles) -> io::Result<()> {
+    stream.write_all(&pid.to_le_bytes())?;
+
+    let mut buf = Vec::new();
+    loop {
+        let mut op = [0_u8; 1];
+        stream.read_exact(&mut op)?;
+
+        let result = match op[0] {
+            OP_READ => {
+                let mut request = [0_u8; 12];
+                stream.read_exact(&mut request)?;
+                let addr = u64::from_le_bytes(request[..8].try_into().unwrap());
+                let len = u32::from_le_bytes(request[8..].try_into().unwrap()) as usize;
+
+                if len > MAX_READ {
+                    Err(io::Error::from_raw_os_error(libc::EINVAL))
+                } else {
+                    buf.resize(len, 0);
+                    files.mem.read_exact_at(&mut buf, addr)
+                }
+            }
+            OP_MAPS => read_maps(&files.maps).map(|maps| {
+                buf.clear();
+                buf.extend((maps.len() as u32).to_le_bytes());
+                buf.extend(maps);
+            }),
+            other => {
+                // The rest of the stream can not be understood either
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unknown operation {}", other),
+                ));
+            }
+        };
+
+        match result {
+            Ok(()) => {
+                stream.write_all(&[STATUS_OK])?;
+                stream.write_all(&buf)?;
+            }
+            Err(e) => {
+                let errno = e.raw_os_error().unwrap_or(libc::EIO);
+                stream.write_all(&[STATUS_ERR])?;
+                stream.write_all(&errno.to_le_bytes())?;
+            }
+        }
+    }
+}
+
+/// Reads the whole maps file from the start, which generates a fresh snapshot every time
+fn read_maps(maps: &File) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    let mut chunk = [0_u8; 4096];
+    loop {
+        match maps.read_at(&mut chunk, contents.len() as u64)? {
+            0 => return Ok(contents),
+            read => contents.extend_from_slice(&chunk[..read]),
+        }
+    }
+}
+
+pub(crate) fn same_user(stream: &UnixStream) -> bool {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len are valid for writes and len is the size of cred
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    // SAFETY: getuid has no preconditions and cannot fail
+    res == 0 && cred.uid == unsafe { libc::getuid() }
+}
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+fn drop_capabilities() -> io::Result<()> {
+    let header = CapUserHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    // Version 3 capabilities are split over two 32 bit sets
+    let data = [CapUserData {
+        effective: 0,
+        permitted: 0,
+        inheritable: 0,
+    }; 2];
+
+    // SAFETY: header and data have the layout capset expects for version 3
+    let res = unsafe { libc::syscall(libc::SYS_capset, &header, data.as_ptr()) };
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
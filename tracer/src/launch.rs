@@ -0,0 +1,143 @@
+use std::{
+    error::Error,
+    fmt, io,
+    path::PathBuf,
+    process::{Child, Command, ExitStatus},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{discovery, Celeste, TraceError};
+
+/// How to start the game so that it is a child of this process.
+/// Yama's default `ptrace_scope` of 1 only allows reading the memory of descendants,
+/// so launching the game ourselves avoids needing root.
+#[derive(Clone, Debug, Default)]
+pub struct LaunchOptions {
+    // Either the `Celeste.bin.x86_64` launcher or the directory containing it
+    pub celeste: PathBuf,
+    // A wrapper that is run with the game as its first argument, like the Steam runtime's `run.sh`
+    pub wrapper: Option<PathBuf>,
+    pub args: Vec<String>,
+    // How long to wait for the game to finish starting, or None to wait forever
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+pub enum LaunchError {
+    Spawn(io::Error),
+    /// The launched process exited before Celeste finished starting
+    Exited(ExitStatus),
+    /// Celeste did not finish starting in time, with the last error from trying to attach.
+    /// The game was stopped.
+    TimedOut(Option<TraceError>),
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::Spawn(e) => write!(f, "Unable to start Celeste: {}", e),
+            LaunchError::Exited(status) => write!(f, "Celeste exited while starting ({})", status),
+            LaunchError::TimedOut(None) => write!(f, "Timed out waiting for Celeste to start"),
+            LaunchError::TimedOut(Some(e)) => {
+                write!(f, "Timed out waiting for Celeste to start: {}", e)
+            }
+        }
+    }
+}
+
+impl Error for LaunchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LaunchError::Spawn(e) => Some(e),
+            LaunchError::TimedOut(Some(e)) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+pub struct LaunchedCeleste {
+    // The process that was spawned, which is the wrapper if one was used
+    pub child: Child,
+    // The pid of the game itself
+    pub pid: u32,
+    pub celeste: Celeste,
+}
+
+/// Starts Celeste as a child process and waits until Mono has loaded the game far enough to attach
+pub fn launch_celeste(options: &LaunchOptions) -> Result<LaunchedCeleste, LaunchError> {
+    let exe = if options.celeste.is_dir() {
+        options.celeste.join("Celeste.bin.x86_64")
+    } else {
+        options.celeste.clone()
+    };
+
+    let mut command = match &options.wrapper {
+        Some(wrapper) => {
+            let mut command = Command::new(wrapper);
+            command.arg(&exe);
+            command
+        }
+        None => Command::new(&exe),
+    };
+    command.args(&options.args);
+    // The game loads its content relative to the working directory
+    if let Some(game_dir) = exe.parent() {
+        command.current_dir(game_dir);
+    }
+
+    let mut child = command.spawn().map_err(LaunchError::Spawn)?;
+    let start = Instant::now();
+    let mut last_error = None;
+    // The game itself once it was found, which is not the child when a wrapper started it
+    let mut game_pid = None;
+
+    loop {
+        if let Some(status) = child.try_wait().map_err(LaunchError::Spawn)? {
+            return Err(LaunchError::Exited(status));
+        }
+
+        if let Some(pid) = find_game_pid(&child, options.wrapper.is_some()) {
+            game_pid = Some(pid);
+            match Celeste::new(pid) {
+                Ok(celeste) => {
+                    return Ok(LaunchedCeleste {
+                        child,
+                        pid,
+                        celeste,
+                    })
+                }
+                // Mono has not finished loading the game yet
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        if let Some(timeout) = options.timeout {
+            if start.elapsed() >= timeout {
+                // Nothing is going to attach to the game anymore, so do not leave it running
+                if let Some(pid) = game_pid.filter(|&pid| pid != child.id()) {
+                    // SAFETY: kill has no preconditions
+                    unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+                }
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(LaunchError::TimedOut(last_error));
+            }
+        }
+
+        thread::sleep(Duration::from_millis(500));
+    }
+}
+
+fn find_game_pid(child: &Child, wrapped: bool) -> Option<u32> {
+    if !wrapped {
+        return Some(child.id());
+    }
+
+    // Wrappers start the game as a (possibly indirect) child of their own
+    discovery::find_celeste()
+        .ok()?
+        .into_iter()
+        .map(|candidate| candidate.pid)
+        .find(|&pid| discovery::is_descendant(pid, child.id()))
+}
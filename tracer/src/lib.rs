@@ -1,63 +1,52 @@
-#![feature(const_evaluatable_checked, const_generics)]
-#![allow(incomplete_features)]
 #![deny(unsafe_op_in_unsafe_fn)]
 
 use std::{
-    fs::{self, File},
-    io::{Seek, SeekFrom, Write},
-    mem,
-    path::Path,
-    process,
-    sync::{Arc, Mutex},
+    convert::TryInto,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::Mutex,
     thread,
     time::Duration,
-    usize,
 };
 
+#[cfg(feature = "capi")]
+mod capi;
+mod diagnostics;
+mod discovery;
+mod events;
+mod format;
+mod helper;
+mod launch;
+mod maps;
+mod server;
+mod shm;
+mod source;
 mod tracer;
+#[cfg(feature = "capi")]
+pub use crate::capi::*;
+pub use crate::diagnostics::*;
+pub use crate::discovery::*;
+pub use crate::events::*;
+pub use crate::format::*;
+pub use crate::helper::*;
+pub use crate::launch::*;
+pub use crate::maps::*;
+pub use crate::server::*;
+pub use crate::shm::*;
+pub use crate::source::*;
 use crate::tracer::*;
+pub use crate::tracer::{Pod, TraceError};
 
 #[cfg(not(target_os = "linux"))]
 compile_error!("This program does not support non-linux OSes, please use a Linux OS :)");
 
-#[derive(Clone, Copy, Debug)]
-pub enum PIDError {
-    NotFound,
-    IOError,
-}
-
-pub fn find_celeste() -> Result<u32, PIDError> {
-    for dir in fs::read_dir(Path::new("/proc/")).unwrap() {
-        if let Ok(dir) = dir {
-            if let Ok(file_type) = dir.file_type() {
-                if file_type.is_dir() {
-                    let name = dir.file_name().into_string().unwrap();
-                    if name.chars().all(|c| ('0'..='9').contains(&c)) {
-                        if let Ok(path) = fs::read_link(&format!("/proc/{}/exe", name)) {
-                            if path
-                                .into_os_string()
-                                .into_string()
-                                .unwrap()
-                                .contains("Celeste.bin.x86_64")
-                            {
-                                return Ok(str::parse(&name).unwrap());
-                            }
-                        }
-                    }
-                }
-            } else {
-                return Err(PIDError::IOError);
-            }
-        } else {
-            return Err(PIDError::IOError);
-        }
-    }
-
-    Err(PIDError::NotFound)
-}
-
 #[derive(Debug)]
 pub struct Celeste {
+    domain: usize,
+    domain_name: String,
     assembly: usize,
     class_cache: usize,
     celeste_class: usize,
@@ -66,167 +55,268 @@ pub struct Celeste {
     level_class: usize,
     instance: usize,
     autosplitter_info: usize,
+    memory: Mutex<Memory>,
+
+    // The raw AutoSplitterInfo of the last consistent dump and its generation
+    last_snapshot: Mutex<([u8; AutosplitterInfo::SIZE], u64)>,
 }
 
-impl Celeste {
-    fn init(pid: u32) -> usize {
-        MEM_FILE.get_or_init(|| Arc::new(Mutex::new(Some(load_mem(pid)))));
+/// How often to try reading a consistent dump before giving up for this poll
+const MAX_SNAPSHOT_ATTEMPTS: usize = 8;
 
-        unsafe {
-            //let root_domain_ptr = read_u64(0xA17650, &mut mem_file) as usize;
-            let domains_list = read_u64(0xA17698) as usize;
+impl Celeste {
+    fn init(mem: &mut Memory) -> Result<(usize, String), TraceError> {
+        //let root_domain_ptr = read_u64(0xA17650, &mut mem_file) as usize;
+        let domains_list = read_u64(mem, 0xA17698)? as usize;
+
+        let first_domain = read_u64(mem, domains_list)? as usize;
+        let first_domain_name_ptr = read_u64(mem, first_domain + 0xD8)? as usize;
+        let first_domain_name = read_string(mem, first_domain_name_ptr)?;
+
+        if first_domain_name != "Celeste.exe" {
+            return Err(TraceError::NotCeleste(format!(
+                "found domain {}",
+                first_domain_name
+            )));
+        }
 
-            let first_domain = read_u64(domains_list) as usize;
-            let first_domain_name_ptr = read_u64(first_domain + 0xD8) as usize;
-            let first_domain_name = read_string(first_domain_name_ptr as usize);
+        let second_domain = read_u64(mem, domains_list + 8)? as usize;
 
-            if first_domain_name != "Celeste.exe" {
-                eprintln!("This is not celeste!  Found {}", first_domain_name);
-                process::exit(1);
-            }
+        let (domain, name) = if second_domain != 0 {
+            let second_domain_name_ptr = read_u64(mem, second_domain + 0xD8)? as usize;
+            let second_domain_name = read_string(mem, second_domain_name_ptr)?;
+            (second_domain, second_domain_name)
+        } else {
+            (first_domain, first_domain_name)
+        };
 
-            let second_domain = read_u64(domains_list + 8) as usize;
+        Ok((domain, name))
+    }
 
-            let (domain, name) = if second_domain != 0 {
-                let second_domain_name_ptr = read_u64(second_domain + 0xD8) as usize;
-                let second_domain_name = read_string(second_domain_name_ptr as usize);
-                (second_domain, second_domain_name)
-            } else {
-                (first_domain, first_domain_name)
-            };
+    pub fn new(pid: u32) -> Result<Self, TraceError> {
+        Self::with_source(Box::new(ProcMem::new(pid, load_mem(pid)?)))
+    }
 
-            println!("Connected to {}", name);
-            domain
+    /// Attaches to the Celeste whose memory is provided by `source`
+    pub fn with_source(source: Box<dyn MemorySource>) -> Result<Self, TraceError> {
+        let mut memory = Memory::new(source);
+        let mem = &mut memory;
+        let (domain, domain_name) = Self::init(mem)?;
+        let assembly = read_u64(mem, domain + 0xD0)? as usize;
+        let image = read_u64(mem, assembly + 0x60)? as usize;
+        let class_cache = image + 1216;
+        let celeste_class = lookup_class(mem, class_cache, "Celeste")?;
+        let savedata_class = lookup_class(mem, class_cache, "SaveData")?;
+        let engine_class = lookup_class(mem, class_cache, "Engine")?;
+        let level_class = lookup_class(mem, class_cache, "Level")?;
+
+        let instance = static_field_u64(mem, celeste_class, "Instance")? as usize;
+        if instance == 0 {
+            return Err(TraceError::Missing("the Celeste instance".to_string()));
         }
+        let autosplitter_info = locate_autosplitter_info(mem, instance)?;
+
+        Ok(Celeste {
+            domain,
+            domain_name,
+            assembly,
+            class_cache,
+            celeste_class,
+            savedata_class,
+            engine_class,
+            level_class,
+            instance,
+            autosplitter_info,
+            memory: Mutex::new(memory),
+            last_snapshot: Mutex::new(([0; AutosplitterInfo::SIZE], 0)),
+        })
     }
 
-    pub fn new(pid: u32) -> Self {
-        let domain = Self::init(pid);
-        unsafe {
-            let assembly = read_u64(domain + 0xD0) as usize;
-            let image = read_u64(assembly + 0x60) as usize;
-            let class_cache = image + 1216;
-            let celeste_class = lookup_class(class_cache, "Celeste");
-            let savedata_class = lookup_class(class_cache, "SaveData");
-            let engine_class = lookup_class(class_cache, "Engine");
-            let level_class = lookup_class(class_cache, "Level");
-
-            let instance = static_field_u64(celeste_class as usize, "Instance") as usize;
-            let autosplitter_info = locate_autosplitter_info(instance);
-
-            Celeste {
-                assembly,
-                class_cache,
-                celeste_class,
-                savedata_class,
-                engine_class,
-                level_class,
-                instance,
-                autosplitter_info,
+    /// The name of the Mono domain the game runs in
+    pub fn domain_name(&self) -> &str {
+        &self.domain_name
+    }
+
+    /// The addresses of everything resolved while attaching, for debugging
+    pub fn addresses(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("domain", self.domain),
+            ("assembly", self.assembly),
+            ("class cache", self.class_cache),
+            ("class Celeste", self.celeste_class),
+            ("class SaveData", self.savedata_class),
+            ("class Engine", self.engine_class),
+            ("class Level", self.level_class),
+            ("Celeste.Instance", self.instance),
+            ("AutoSplitterInfo", self.autosplitter_info),
+        ]
+    }
+
+    /// Reads raw game memory at `addr`, with the same checks as every other read
+    pub fn read_memory(&self, addr: usize, buf: &mut [u8]) -> Result<(), TraceError> {
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        memory.read(addr, buf)
+    }
+
+    /// The mappings of the game, or None if its memory source does not provide them
+    pub fn memory_map(&self) -> Option<MemoryMap> {
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        memory.map().cloned()
+    }
+
+    /// Reads the `System.Version` of the game
+    pub fn game_version(&self) -> Result<String, TraceError> {
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        let mem = &mut *memory;
+
+        let version = match static_field_u64(mem, self.celeste_class, "Version") {
+            Ok(version) => version,
+            Err(_) => instance_field_u64(mem, self.instance, "Version")?,
+        } as usize;
+        if version == 0 {
+            return Err(TraceError::Missing("the game version".to_string()));
+        }
+
+        let mut parts = Vec::new();
+        for field in ["_Major", "_Minor", "_Build", "_Revision"] {
+            // Unset parts are -1
+            let part = instance_field_u32(mem, version, field)? as i32;
+            if part < 0 {
+                break;
             }
+            parts.push(part.to_string());
         }
+        Ok(parts.join("."))
     }
 
-    pub fn get_data(&self) -> Dump {
-        unsafe {
-            // Looks like the assembly should always start with 0x0000000000000001
-            // Not entirely foolproof, but might allow for better detection of closing
-            if read_u64(self.assembly) != 1 {
-                // Dump some info for possible debug purposes if env is set
-                if let Some(backtrace) = option_env!("RUST_BACKTRACE") {
-                    if !backtrace.is_empty() {
-                        println!("Assembly at {:#08X} didn't match expected first 8 bytes.  Dumping first KiB:", self.assembly);
-                        let mut buf = vec![0_u8; 1024];
-                        MemPtr::new(self.assembly).read_into(&mut buf);
-                        buf.set_len(1024);
-
-                        for i in 0..64 {
-                            for j in 0..16 {
-                                print!("{:02X} ", buf[i * 16 + j]);
-                            }
-                            println!();
+    pub fn get_data(&self) -> Result<Dump, TraceError> {
+        let mut memory = self.memory.lock().expect("Unable to lock memory");
+        let mem = &mut *memory;
+
+        // Looks like the assembly should always start with 0x0000000000000001
+        // Not entirely foolproof, but might allow for better detection of closing
+        if read_u64(mem, self.assembly)? != 1 {
+            // Dump some info for possible debug purposes if env is set
+            if let Some(backtrace) = option_env!("RUST_BACKTRACE") {
+                if !backtrace.is_empty() {
+                    println!("Assembly at {:#08X} didn't match expected first 8 bytes.  Dumping first KiB:", self.assembly);
+                    let mut buf = vec![0_u8; 1024];
+                    MemPtr::new(self.assembly).read_into(mem, &mut buf)?;
+
+                    for i in 0..64 {
+                        for j in 0..16 {
+                            print!("{:02X} ", buf[i * 16 + j]);
                         }
+                        println!();
                     }
                 }
+            }
 
-                process::exit(1);
+            return Err(TraceError::NotCeleste(
+                "the assembly was unloaded".to_string(),
+            ));
+        }
+
+        // The game rewrites AutoSplitterInfo every frame while we read it piece by piece, so only
+        // accept a dump if AutoSplitterInfo did not change while everything else was being read
+        for _ in 0..MAX_SNAPSHOT_ATTEMPTS {
+            let before = self.read_autosplitter_info(mem)?;
+            let asi = AutosplitterInfo::decode(&before)
+                .map_err(|e| TraceError::Decode(self.autosplitter_info, e))?;
+            let dump = self.read_dump(mem, asi);
+            let after = self.read_autosplitter_info(mem)?;
+
+            if before == after {
+                let mut dump = dump?;
+                let mut last = self.last_snapshot.lock().expect("Unable to lock snapshot");
+                if last.0 != before {
+                    *last = (before, last.1 + 1);
+                }
+                dump.generation = last.1;
+                return Ok(dump);
             }
+        }
 
-            let asi: AutosplitterInfo = MemPtr::new(self.autosplitter_info).read();
+        Err(TraceError::Inconsistent(MAX_SNAPSHOT_ATTEMPTS))
+    }
 
-            let mut dump = Dump {
-                autosplitter_info: asi,
-                ..Default::default()
-            };
+    fn read_dump(&self, mem: &mut Memory, asi: AutosplitterInfo) -> Result<Dump, TraceError> {
+        let mut dump = Dump {
+            autosplitter_info: asi,
+            ..Default::default()
+        };
 
-            let savedata_ptr = static_field_u64(self.savedata_class, "Instance") as usize;
-            if savedata_ptr != 0 {
-                // TODO: reimplmement this w/ result maybe?
-                /*
-                if savedata_ptr != last_savedata_ptr {
-                    // TODO: sleep here to give time to save?
-                    last_savedata_ptr = savedata_ptr;
-                    continue;
-                }
-                */
+        if asi.level != 0 {
+            dump.level_name = read_boxed_string(mem, asi.level as usize)?;
+        }
 
-                dump.death_count = instance_field_u32(savedata_ptr, "TotalDeaths");
+        let savedata_ptr = static_field_u64(mem, self.savedata_class, "Instance")? as usize;
+        if savedata_ptr != 0 {
+            // TODO: reimplmement this w/ result maybe?
+            /*
+            if savedata_ptr != last_savedata_ptr {
+                // TODO: sleep here to give time to save?
+                last_savedata_ptr = savedata_ptr;
+                continue;
+            }
+            */
 
-                if asi.chapter == -1 {
-                    // mode stats = 0?
-                } else {
-                    let areas = instance_field_u64(savedata_ptr, "Areas") as usize;
-                    if instance_field_u32(areas, "_size") == 11 {
-                        let areas_ptr = instance_field_u64(areas, "_items") as usize;
-                        let area_stats =
-                            read_u64(areas_ptr + 0x20 + 8 * asi.chapter as usize) as usize;
-                        let mode_arr = instance_field_u64(area_stats, "Modes") as usize + 0x20;
-                        let mode_stats = read_u64(mode_arr + 8 * asi.mode as usize) as usize;
-                        if mode_stats == 0 {
-                            dump.chapter_checkpoints = 0;
-                        } else {
-                            let checkpoints =
-                                instance_field_u64(mode_stats, "Checkpoints") as usize;
-                            dump.chapter_checkpoints = instance_field_u32(checkpoints, "_count");
-                        }
+            dump.death_count = instance_field_u32(mem, savedata_ptr, "TotalDeaths")?;
+
+            if asi.chapter == -1 {
+                // mode stats = 0?
+            } else {
+                let areas = instance_field_u64(mem, savedata_ptr, "Areas")? as usize;
+                if instance_field_u32(mem, areas, "_size")? == 11 {
+                    let areas_ptr = instance_field_u64(mem, areas, "_items")? as usize;
+                    let area_stats =
+                        read_u64(mem, areas_ptr + 0x20 + 8 * asi.chapter as usize)? as usize;
+                    let mode_arr = instance_field_u64(mem, area_stats, "Modes")? as usize + 0x20;
+                    let mode_stats = read_u64(mem, mode_arr + 8 * asi.mode as usize)? as usize;
+                    if mode_stats == 0 {
+                        dump.chapter_checkpoints = 0;
                     } else {
-                        eprintln!("Failed to get areas array");
+                        let checkpoints =
+                            instance_field_u64(mem, mode_stats, "Checkpoints")? as usize;
+                        dump.chapter_checkpoints = instance_field_u32(mem, checkpoints, "_count")?;
                     }
+                } else {
+                    eprintln!("Failed to get areas array");
                 }
             }
+        }
 
-            if asi.chapter == -1 || !asi.chapter_started || asi.chapter_complete {
-                dump.in_cutscene = false;
+        if asi.chapter == -1 || !asi.chapter_started || asi.chapter_complete {
+            dump.in_cutscene = false;
+        } else {
+            let scene_offset = class_field_offset(mem, self.engine_class, "scene")?;
+            let scene = read_u64(mem, self.instance + scene_offset)? as usize;
+            if instance_class(mem, scene)? == self.level_class {
+                let in_cutscene_offset = class_field_offset(mem, self.level_class, "InCutscene")?;
+                dump.in_cutscene = read_u8(mem, scene + in_cutscene_offset)? != 0;
             } else {
-                let scene = read_u64(self.instance + class_field_offset(self.engine_class, "scene"))
-                    as usize;
-                if instance_class(scene) == self.level_class {
-                    dump.in_cutscene =
-                        read_u8(scene + class_field_offset(self.level_class, "InCutscene")) != 0;
-                } else {
-                    dump.in_cutscene = false;
-                }
+                dump.in_cutscene = false;
             }
-
-            dump
         }
+
+        Ok(dump)
     }
-}
 
-impl Drop for Celeste {
-    fn drop(&mut self) {
-        if let Some(f) = MEM_FILE.get() {
-            if let Ok(mut file) = f.lock() {
-                *file = None;
-            }
-        }
+    fn read_autosplitter_info(
+        &self,
+        mem: &mut Memory,
+    ) -> Result<[u8; AutosplitterInfo::SIZE], TraceError> {
+        let mut bytes = [0_u8; AutosplitterInfo::SIZE];
+        MemPtr::new(self.autosplitter_info).read_into(mem, &mut bytes)?;
+        Ok(bytes)
     }
 }
 
-#[derive(Clone, Copy, Debug, Default)]
-#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct AutosplitterInfo {
     // ptr to a boxed string of the level (room) name
+    #[cfg_attr(feature = "serde", serde(skip))]
     level: u64,
 
     pub chapter: i32,
@@ -252,7 +342,84 @@ pub struct AutosplitterInfo {
     pub file_hearts: i32,
 }
 
+/// A field of the game's `AutoSplitterInfo` held a value that is not valid for its type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub field: &'static str,
+    pub offset: usize,
+    pub value: u8,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid value {:#04X} for {} at offset {:#X}",
+            self.value, self.field, self.offset
+        )
+    }
+}
+
+impl Error for DecodeError {}
+
 impl AutosplitterInfo {
+    /// The size of `AutoSplitterInfo` in game memory, including padding
+    pub const SIZE: usize = 64;
+
+    /// Decodes the `AutoSplitterInfo` as laid out in game memory, rejecting invalid `bool`s
+    pub fn decode(bytes: &[u8; Self::SIZE]) -> Result<Self, DecodeError> {
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+        let i32_at =
+            |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let bool_at = |field: &'static str, offset: usize| match bytes[offset] {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(DecodeError {
+                field,
+                offset,
+                value,
+            }),
+        };
+
+        Ok(AutosplitterInfo {
+            level: u64_at(0),
+            chapter: i32_at(8),
+            mode: i32_at(12),
+            timer_active: bool_at("timer_active", 16)?,
+            chapter_started: bool_at("chapter_started", 17)?,
+            chapter_complete: bool_at("chapter_complete", 18)?,
+            chapter_time: u64_at(24),
+            chapter_strawberries: i32_at(32),
+            chapter_cassette: bool_at("chapter_cassette", 36)?,
+            chapter_heart: bool_at("chapter_heart", 37)?,
+            file_time: u64_at(40),
+            file_strawberries: i32_at(48),
+            file_cassettes: i32_at(52),
+            file_hearts: i32_at(56),
+        })
+    }
+
+    /// Encodes in the same layout `decode` reads, with zeroed padding
+    pub fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0_u8; Self::SIZE];
+        bytes[0..8].copy_from_slice(&self.level.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.chapter.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.mode.to_le_bytes());
+        bytes[16] = self.timer_active as u8;
+        bytes[17] = self.chapter_started as u8;
+        bytes[18] = self.chapter_complete as u8;
+        bytes[24..32].copy_from_slice(&self.chapter_time.to_le_bytes());
+        bytes[32..36].copy_from_slice(&self.chapter_strawberries.to_le_bytes());
+        bytes[36] = self.chapter_cassette as u8;
+        bytes[37] = self.chapter_heart as u8;
+        bytes[40..48].copy_from_slice(&self.file_time.to_le_bytes());
+        bytes[48..52].copy_from_slice(&self.file_strawberries.to_le_bytes());
+        bytes[52..56].copy_from_slice(&self.file_cassettes.to_le_bytes());
+        bytes[56..60].copy_from_slice(&self.file_hearts.to_le_bytes());
+        bytes
+    }
+
     /// Returns the chapter time in milliseconds
     pub fn chapter_time(&self) -> u64 {
         self.chapter_time / 10_000
@@ -262,9 +429,20 @@ impl AutosplitterInfo {
     pub fn file_time(&self) -> u64 {
         self.file_time / 10_000
     }
+
+    /// Sets the chapter time in milliseconds, as [`AutosplitterInfo::chapter_time`] returns it
+    pub fn set_chapter_time(&mut self, ms: u64) {
+        self.chapter_time = ms * 10_000;
+    }
+
+    /// Sets the file time in milliseconds, as [`AutosplitterInfo::file_time`] returns it
+    pub fn set_file_time(&mut self, ms: u64) {
+        self.file_time = ms * 10_000;
+    }
 }
 
 #[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct Dump {
     pub autosplitter_info: AutosplitterInfo,
 
@@ -273,49 +451,124 @@ pub struct Dump {
 
     pub in_cutscene: bool,
     pub death_count: u32,
+
+    // Increases whenever the game state changes between dumps of the same Celeste,
+    // so dumps with the same generation were read from the same frame
+    pub generation: u64,
+
+    // The name of the current level (room), empty if there is none
+    level_name: String,
 }
 
 impl Dump {
-    pub fn level_name(&self) -> String {
-        if self.autosplitter_info.level == 0 {
-            String::new()
-        } else {
-            read_boxed_string(self.autosplitter_info.level as usize)
-        }
+    pub fn level_name(&self) -> &str {
+        &self.level_name
     }
 
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut data = Vec::with_capacity(176);
-        let asi: [u8; mem::size_of::<AutosplitterInfo>()] =
-            unsafe { mem::transmute(self.autosplitter_info) };
-        data.extend(asi);
-        data.extend(self.chapter_checkpoints.to_ne_bytes());
-        data.push(self.in_cutscene as u8);
-        data.extend([0_u8; 3]);
-        data.extend(self.death_count.to_ne_bytes());
-
-        let level_name = self.level_name();
-        data.extend(level_name.bytes());
-        // padding
-        data.extend(vec![0; 100 - level_name.len()]);
-
-        data
+    /// Sets the name of the current level, for dumps that were not read from the game
+    pub fn set_level_name(&mut self, level_name: &str) {
+        self.level_name = level_name.to_string();
     }
 }
 
-pub fn dump_info_loop(output_file: &str, pid: u32) {
-    let mut output = File::create(output_file).expect("Could not create output file");
-    let celeste = Celeste::new(pid);
-    loop {
-        let dump = celeste.get_data();
+/// Where [`dump_info_loop`] writes its dumps
+#[derive(Clone, Debug)]
+pub enum DumpOutput {
+    /// Overwrite a regular file in place, where readers can see half-written dumps
+    File(PathBuf),
+    /// Publish into a shared region at this path, usually in `/dev/shm`, see [`ShmReader`]
+    SharedMemory(PathBuf),
+    /// Publish into an anonymous shared region, whose path is printed on startup
+    Memfd,
+    /// Append a line per dump to a file, or to stdout if there is no path
+    Lines {
+        format: LineFormat,
+        path: Option<PathBuf>,
+        // Skip dumps that are the same as the previous one
+        only_changes: bool,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineFormat {
+    /// One JSON object per line, see [`Dump::to_json_line`]
+    JsonLines,
+    /// A header followed by a record per line, see [`Dump::to_csv_record`]
+    Csv,
+}
 
-        output
-            .seek(SeekFrom::Start(0))
-            .expect("Unable to overwrite file");
+type WriteDump = dyn FnMut(&Dump) -> io::Result<()>;
+
+/// Keeps writing the latest dump of `celeste` to `output`, polling every `interval`.  Only
+/// returns once the game can no longer be read or the output can no longer be written.
+pub fn dump_info_loop(
+    output: &DumpOutput,
+    celeste: &Celeste,
+    interval: Duration,
+) -> Result<(), TraceError> {
+    let mut write_dump: Box<WriteDump> = match output {
+        DumpOutput::File(path) => {
+            let mut output = File::create(path).map_err(TraceError::Output)?;
+            Box::new(move |dump| {
+                output.seek(SeekFrom::Start(0))?;
+
+                let data = dump.encode();
+                output.write_all(&data)?;
+                // Dumps with a shorter level name would leave the end of the previous one behind
+                output.set_len(data.len() as u64)
+            })
+        }
+        DumpOutput::SharedMemory(_) | DumpOutput::Memfd => {
+            let publisher = match output {
+                DumpOutput::SharedMemory(path) => ShmPublisher::create(path),
+                _ => ShmPublisher::memfd(),
+            }
+            .map_err(TraceError::Output)?;
+            eprintln!("Publishing dumps at {}", publisher.path().display());
+            Box::new(move |dump| publisher.publish(dump))
+        }
+        DumpOutput::Lines {
+            format,
+            path,
+            only_changes,
+        } => {
+            let mut output: Box<dyn Write> = match path {
+                Some(path) => Box::new(BufWriter::new(
+                    File::create(path).map_err(TraceError::Output)?,
+                )),
+                None => Box::new(io::stdout()),
+            };
+            if *format == LineFormat::Csv {
+                writeln!(output, "{}", Dump::csv_header()).map_err(TraceError::Output)?;
+            }
 
-        let data = dump.as_bytes();
-        output.write_all(&data).expect("Unable to overwrite file");
+            let (format, only_changes) = (*format, *only_changes);
+            let mut last_line = None;
+            Box::new(move |dump| {
+                let line = match format {
+                    LineFormat::JsonLines => dump.to_json_line(),
+                    LineFormat::Csv => dump.to_csv_record(),
+                };
+                if only_changes && last_line.as_ref() == Some(&line) {
+                    return Ok(());
+                }
+                writeln!(output, "{}", line)?;
+                // Keep the output usable by pipelines following it live
+                output.flush()?;
+                last_line = Some(line);
+                Ok(())
+            })
+        }
+    };
+
+    loop {
+        match celeste.get_data() {
+            Ok(dump) => write_dump(&dump).map_err(TraceError::Output)?,
+            // The game was mid-update every time, try again next poll
+            Err(TraceError::Inconsistent(_)) => {}
+            Err(e) => return Err(e),
+        }
 
-        thread::sleep(Duration::from_millis(12));
+        thread::sleep(interval);
     }
 }
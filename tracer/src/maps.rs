@@ -0,0 +1,216 @@
+use std::{fmt, time::Instant};
+
+/// A single line of `/proc/<pid>/maps`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    pub start: usize,
+    pub end: usize,
+    // The `rwxp` permission string
+    pub perms: String,
+    // The backing file or pseudo path like `[heap]`, empty for anonymous mappings
+    pub path: String,
+}
+
+impl Mapping {
+    pub fn readable(&self) -> bool {
+        self.perms.starts_with('r')
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(6, ' ');
+        let (start, end) = fields.next()?.split_once('-')?;
+        let perms = fields.next()?.to_string();
+        // offset, device and inode are not interesting
+        fields.nth(2)?;
+        let path = fields.next().unwrap_or("").trim().to_string();
+
+        Some(Mapping {
+            start: usize::from_str_radix(start, 16).ok()?,
+            end: usize::from_str_radix(end, 16).ok()?,
+            perms,
+            path,
+        })
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#X}-{:#X} {}", self.start, self.end, self.perms)?;
+        if !self.path.is_empty() {
+            write!(f, " {}", self.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// The mapping table of a process at some point in time
+#[derive(Clone, Debug)]
+pub struct MemoryMap {
+    // Sorted by start address and never overlapping, as the kernel reports them
+    mappings: Vec<Mapping>,
+    loaded_at: Instant,
+}
+
+impl MemoryMap {
+    /// Parses the contents of `/proc/<pid>/maps`, skipping lines it does not understand
+    pub fn parse(maps: &str) -> Self {
+        let mut mappings = maps.lines().filter_map(Mapping::parse).collect::<Vec<_>>();
+        mappings.sort_by_key(|m| m.start);
+        MemoryMap {
+            mappings,
+            loaded_at: Instant::now(),
+        }
+    }
+
+    pub fn mappings(&self) -> &[Mapping] {
+        &self.mappings
+    }
+
+    pub fn loaded_at(&self) -> Instant {
+        self.loaded_at
+    }
+
+    /// Returns the mapping containing `addr`
+    pub fn find(&self, addr: usize) -> Option<&Mapping> {
+        let idx = self.mappings.partition_point(|m| m.end <= addr);
+        self.mappings.get(idx).filter(|m| m.start <= addr)
+    }
+
+    /// Returns the mapping closest to `addr`, which is the one containing it if there is one
+    pub fn nearest(&self, addr: usize) -> Option<&Mapping> {
+        let idx = self.mappings.partition_point(|m| m.end <= addr);
+        let after = self.mappings.get(idx);
+        let before = idx.checked_sub(1).and_then(|idx| self.mappings.get(idx));
+        match (before, after) {
+            (Some(before), Some(after)) => {
+                if after.start <= addr || after.start - addr < addr - before.end {
+                    Some(after)
+                } else {
+                    Some(before)
+                }
+            }
+            (before, after) => before.or(after),
+        }
+    }
+
+    /// Returns whether all of `addr..addr + len` is covered by readable mappings
+    pub fn is_readable(&self, addr: usize, len: usize) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+
+        // A read may span several adjacent mappings
+        let mut current = addr;
+        loop {
+            match self.find(current) {
+                Some(mapping) if mapping.readable() => {
+                    if mapping.end >= end {
+                        return true;
+                    }
+                    current = mapping.end;
+                }
+                _ => return false,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPS: &str = "\
+00400000-00401000 r-xp 00000000 08:01 1234                               /usr/bin/mono-sgen
+00401000-00402000 rw-p 00001000 08:01 1234                               /usr/bin/mono-sgen
+01a00000-01b00000 rw-p 00000000 00:00 0                                  [heap]
+40000000-40010000 r--p 00000000 08:01 5678                               /home/me/Steam Library/Celeste/Celeste.exe
+40010000-40020000 rw-p 00000000 00:00 0 
+40020000-40030000 ---p 00000000 00:00 0 
+40030000-40040000 r--p 00000000 00:00 0 
+not a mapping
+7ffc0000-7ffd0000 rw-p 00000000 00:00 0                                  [stack]
+";
+
+    fn map() -> MemoryMap {
+        MemoryMap::parse(MAPS)
+    }
+
+    #[test]
+    fn parses_mappings() {
+        let map = map();
+        let mappings = map.mappings();
+        assert_eq!(mappings.len(), 8);
+        assert_eq!(
+            mappings[0],
+            Mapping {
+                start: 0x400000,
+                end: 0x401000,
+                perms: "r-xp".to_string(),
+                path: "/usr/bin/mono-sgen".to_string(),
+            }
+        );
+        assert_eq!(mappings[2].path, "[heap]");
+        assert_eq!(
+            mappings[3].path,
+            "/home/me/Steam Library/Celeste/Celeste.exe"
+        );
+        assert_eq!(mappings[4].path, "");
+        assert!(!mappings[5].readable());
+        assert_eq!(mappings[7].path, "[stack]");
+    }
+
+    #[test]
+    fn finds_the_mapping_containing_an_address() {
+        let map = map();
+        assert_eq!(map.find(0x400000).unwrap().start, 0x400000);
+        assert_eq!(map.find(0x400fff).unwrap().start, 0x400000);
+        assert_eq!(map.find(0x401000).unwrap().start, 0x401000);
+        assert_eq!(map.find(0x1a80000).unwrap().path, "[heap]");
+        assert!(map.find(0).is_none());
+        assert!(map.find(0x402000).is_none());
+        assert!(map.find(0x7ffd0000).is_none());
+        assert!(map.find(usize::MAX).is_none());
+    }
+
+    #[test]
+    fn finds_the_nearest_mapping() {
+        let map = map();
+        assert_eq!(map.nearest(0x1a80000).unwrap().path, "[heap]");
+        assert_eq!(map.nearest(0).unwrap().start, 0x400000);
+        assert_eq!(map.nearest(0x402000).unwrap().start, 0x401000);
+        assert_eq!(map.nearest(0x19fffff).unwrap().path, "[heap]");
+        assert_eq!(map.nearest(usize::MAX).unwrap().path, "[stack]");
+        assert!(MemoryMap::parse("").nearest(0x1000).is_none());
+    }
+
+    #[test]
+    fn readable_within_a_mapping() {
+        let map = map();
+        assert!(map.is_readable(0x1a00000, 0x100000));
+        assert!(map.is_readable(0x1afffff, 1));
+        assert!(map.is_readable(0x1a00000, 0));
+        assert!(!map.is_readable(0x1afffff, 2));
+        assert!(!map.is_readable(0x1b00000, 1));
+        assert!(!map.is_readable(0x402000, 0));
+    }
+
+    #[test]
+    fn readable_across_adjacent_mappings() {
+        let map = map();
+        assert!(map.is_readable(0x400ff0, 0x20));
+        assert!(map.is_readable(0x400000, 0x2000));
+        assert!(map.is_readable(0x4000fff0, 0x10010));
+        // Not readable past the end of the last adjacent mapping
+        assert!(!map.is_readable(0x400000, 0x2001));
+        // Nor into an adjacent mapping that can not be read
+        assert!(!map.is_readable(0x4001fff0, 0x20));
+        assert!(!map.is_readable(0x40020000, 1));
+        assert!(!map.is_readable(0x4001fff0, 0x20010));
+    }
+
+    #[test]
+    fn unreadable_when_the_range_overflows() {
+        assert!(!map().is_readable(0x7ffc0000, usize::MAX));
+    }
+}
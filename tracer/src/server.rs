@@ -0,0 +1,296 @@
+//! Streams the state of the game to any number of local clients over a Unix socket.
+//!
+//! Every message in either direction is a frame of a little endian `u32` length followed by
+//! that many bytes of UTF-8.
+//!
+//! The server sends JSON objects with a `type` of
+//! - `hello` on connecting, with the protocol `version` and the `kinds` that can be subscribed to
+//! - `dump` with the latest `dump`, as in [`Dump::to_json_line`], whenever the game state changes
+//! - `event` with an `event`, as in [`GameEvent::to_json`], whenever something happens in game
+//! - `snapshot` with the latest `dump`, or `null` before the first, in answer to `snapshot`
+//! - `error` with a `message` when a request was not understood
+//!
+//! Clients send requests as plain text
+//! - `snapshot` asks for the latest dump
+//! - `subscribe <kind>...` only sends the listed kinds from now on, which are `dump`, the
+//!   kinds of [`GameEvent::KINDS`], or `all`.  New clients are subscribed to all of them.
+//!
+//! Clients that stop reading are disconnected instead of holding up the others.
+
+use std::{
+    collections::HashSet,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    format::json_string,
+    helper::{bind_private, same_user},
+    Celeste, Dump, GameEvent, TraceError,
+};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// How many messages may wait to be written to a client before it is disconnected
+const QUEUE_LEN: usize = 64;
+/// Requests longer than this are rejected, since none of them need to be
+const MAX_REQUEST: usize = 4096;
+
+struct Client {
+    // Frames waiting for the thread writing to this client
+    queue: SyncSender<Vec<u8>>,
+    // Only used to disconnect, which also stops the threads reading from and writing to it
+    stream: UnixStream,
+    // The kinds this client wants to receive
+    subscriptions: Mutex<HashSet<&'static str>>,
+}
+
+impl Client {
+    /// Starts the thread writing to `stream`, so a slow client never blocks the sender
+    fn new(stream: UnixStream) -> io::Result<Self> {
+        let mut writer = stream.try_clone()?;
+        let (queue, frames) = mpsc::sync_channel::<Vec<u8>>(QUEUE_LEN);
+        thread::spawn(move || {
+            // Ends once the client is dropped, which closes the queue
+            for frame in frames {
+                if writer.write_all(&frame).is_err() {
+                    let _ = writer.shutdown(Shutdown::Both);
+                    return;
+                }
+            }
+        });
+
+        Ok(Self {
+            queue,
+            stream,
+            subscriptions: Mutex::new(all_kinds().collect()),
+        })
+    }
+
+    /// Queues `message` without waiting, failing if the client is not keeping up or gone
+    fn send(&self, message: &str) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(4 + message.len());
+        frame.extend((message.len() as u32).to_le_bytes());
+        frame.extend(message.as_bytes());
+        self.queue.try_send(frame).map_err(|e| match e {
+            TrySendError::Full(_) => {
+                io::Error::new(io::ErrorKind::WouldBlock, "client is not keeping up")
+            }
+            TrySendError::Disconnected(_) => io::ErrorKind::BrokenPipe.into(),
+        })
+    }
+
+    fn is_subscribed(&self, kind: &str) -> bool {
+        self.subscriptions
+            .lock()
+            .expect("Unable to lock client")
+            .contains(kind)
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    clients: Mutex<Vec<Arc<Client>>>,
+    latest: Mutex<Option<Dump>>,
+}
+
+impl Shared {
+    /// Sends `message` to every client subscribed to `kind`, dropping the ones that fail
+    fn broadcast(&self, kind: &str, message: &str) {
+        let mut clients = self.clients.lock().expect("Unable to lock clients");
+        clients.retain(|client| {
+            if !client.is_subscribed(kind) {
+                return true;
+            }
+            match client.send(message) {
+                Ok(()) => true,
+                Err(_) => {
+                    // Also stops the thread reading its requests
+                    let _ = client.stream.shutdown(Shutdown::Both);
+                    false
+                }
+            }
+        });
+    }
+}
+
+/// Every kind a client can subscribe to
+fn all_kinds() -> impl Iterator<Item = &'static str> {
+    std::iter::once("dump").chain(GameEvent::KINDS.iter().copied())
+}
+
+/// Polls `celeste` every `interval` and streams its state on `socket_path` until the game is lost
+pub fn serve_state<P: AsRef<Path>>(
+    celeste: &Celeste,
+    socket_path: P,
+    interval: Duration,
+) -> io::Result<()> {
+    let socket_path = socket_path.as_ref();
+    let listener = bind_private(socket_path)?;
+    eprintln!("Serving game state on {}", socket_path.display());
+
+    let shared = Arc::new(Shared::default());
+    {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || accept_clients(listener, &shared));
+    }
+
+    let mut last: Option<(Dump, String)> = None;
+    loop {
+        let dump = match celeste.get_data() {
+            Ok(dump) => dump,
+            // The game was mid-update every time, try again next poll
+            Err(TraceError::Inconsistent(_)) => {
+                thread::sleep(interval);
+                continue;
+            }
+            Err(e) => return Err(io::Error::other(e)),
+        };
+
+        let json = dump.to_json_line();
+        if last.as_ref().map(|(_, last_json)| last_json) != Some(&json) {
+            *shared.latest.lock().expect("Unable to lock dump") = Some(dump.clone());
+            shared.broadcast("dump", &format!("{{\"type\":\"dump\",\"dump\":{}}}", json));
+
+            if let Some((prev, _)) = &last {
+                for event in GameEvent::between(prev, &dump) {
+                    shared.broadcast(
+                        event.kind(),
+                        &format!("{{\"type\":\"event\",\"event\":{}}}", event.to_json()),
+                    );
+                }
+            }
+            last = Some((dump, json));
+        }
+
+        thread::sleep(interval);
+    }
+}
+
+fn accept_clients(listener: UnixListener, shared: &Arc<Shared>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Unable to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        if !same_user(&stream) {
+            eprintln!("Rejected a connection from another user");
+            continue;
+        }
+
+        let (reader, client) = match stream
+            .try_clone()
+            .and_then(|reader| Ok((reader, Client::new(stream)?)))
+        {
+            Ok((reader, client)) => (reader, Arc::new(client)),
+            Err(e) => {
+                eprintln!("Unable to set up connection: {}", e);
+                continue;
+            }
+        };
+        let kinds = all_kinds().map(json_string).collect::<Vec<_>>().join(",");
+        let hello = format!(
+            "{{\"type\":\"hello\",\"version\":{},\"kinds\":[{}]}}",
+            PROTOCOL_VERSION, kinds
+        );
+        if client.send(&hello).is_err() {
+            continue;
+        }
+
+        shared
+            .clients
+            .lock()
+            .expect("Unable to lock clients")
+            .push(Arc::clone(&client));
+
+        let shared = Arc::clone(shared);
+        thread::spawn(move || {
+            if let Err(e) = serve_requests(reader, &client, &shared) {
+                if e.kind() != io::ErrorKind::UnexpectedEof {
+                    eprintln!("Client disconnected: {}", e);
+                }
+            }
+            shared
+                .clients
+                .lock()
+                .expect("Unable to lock clients")
+                .retain(|other| !Arc::ptr_eq(other, &client));
+        });
+    }
+}
+
+fn serve_requests(mut reader: UnixStream, client: &Client, shared: &Shared) -> io::Result<()> {
+    loop {
+        let mut len = [0_u8; 4];
+        reader.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+        if len > MAX_REQUEST {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("request of {} bytes is too long", len),
+            ));
+        }
+        let mut request = vec![0_u8; len];
+        reader.read_exact(&mut request)?;
+        let request = String::from_utf8_lossy(&request);
+
+        let mut words = request.split_whitespace();
+        let answer = match words.next() {
+            Some("snapshot") => {
+                let latest = shared.latest.lock().expect("Unable to lock dump");
+                let dump = latest
+                    .as_ref()
+                    .map_or_else(|| "null".to_string(), |dump| dump.to_json_line());
+                format!("{{\"type\":\"snapshot\",\"dump\":{}}}", dump)
+            }
+            Some("subscribe") => match subscriptions(words) {
+                Ok(kinds) => {
+                    *client.subscriptions.lock().expect("Unable to lock client") = kinds;
+                    continue;
+                }
+                Err(unknown) => error_message(&format!("unknown kind {}", unknown)),
+            },
+            _ => error_message(&format!("unknown request {}", request.trim())),
+        };
+        client.send(&answer)?;
+    }
+}
+
+/// Parses the kinds of a `subscribe` request, returning the first unknown one on failure
+fn subscriptions<'a, I: Iterator<Item = &'a str>>(
+    words: I,
+) -> Result<HashSet<&'static str>, String> {
+    let mut kinds = HashSet::new();
+    for word in words {
+        if word == "all" {
+            kinds.extend(all_kinds());
+            continue;
+        }
+        match all_kinds().find(|&kind| kind == word) {
+            Some(kind) => {
+                kinds.insert(kind);
+            }
+            None => return Err(word.to_string()),
+        }
+    }
+    Ok(kinds)
+}
+
+fn error_message(message: &str) -> String {
+    format!(
+        "{{\"type\":\"error\",\"message\":{}}}",
+        json_string(message)
+    )
+}
@@ -0,0 +1,370 @@
+//! Publishes dumps through shared memory, so readers can poll them as often as they like
+//! without syscalls and without ever seeing a half-written dump.
+//!
+//! The region is [`REGION_SIZE`] bytes of little endian `u64`s:
+//! - a sequence number, which is odd while a dump is being written and 0 before the first
+//! - the length of the dump in bytes
+//! - the dump as written by [`Dump::encode`], padded with zeroes to whole `u64`s
+//!
+//! Readers load the sequence number, copy the dump, and load the sequence number again.
+//! If it changed or was odd the copy may be torn and has to be retried.
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    hint, io,
+    os::unix::{
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    process, ptr, slice,
+    sync::atomic::{fence, AtomicU64, Ordering},
+};
+
+use crate::{Dump, FormatError};
+
+/// The size of the shared region, which limits the size of a dump
+pub const REGION_SIZE: usize = 4096;
+
+const HEADER_WORDS: usize = 2;
+
+/// How often a reader retries while the writer keeps changing the dump
+const MAX_READ_ATTEMPTS: usize = 1000;
+
+/// A shared mapping of a region file
+struct Region {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is only accessed through atomics
+unsafe impl Send for Region {}
+unsafe impl Sync for Region {}
+
+impl Region {
+    fn map(file: &File, writable: bool) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len < REGION_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("shared region is {} bytes instead of {}", len, REGION_SIZE),
+            ));
+        }
+
+        let prot = if writable {
+            libc::PROT_READ | libc::PROT_WRITE
+        } else {
+            libc::PROT_READ
+        };
+        // SAFETY: mapping a file we hold open, which stays valid after the file is closed
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                REGION_SIZE,
+                prot,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Region {
+            ptr: ptr as *mut u8,
+            len: REGION_SIZE,
+        })
+    }
+
+    fn words(&self) -> &[AtomicU64] {
+        // SAFETY: the mapping is page aligned, REGION_SIZE bytes long and lives as long as self.
+        // Other processes only access it through atomics as well.
+        unsafe { slice::from_raw_parts(self.ptr as *const AtomicU64, self.len / 8) }
+    }
+
+    fn seq(&self) -> &AtomicU64 {
+        &self.words()[0]
+    }
+
+    fn dump_len(&self) -> &AtomicU64 {
+        &self.words()[1]
+    }
+
+    fn data(&self) -> &[AtomicU64] {
+        &self.words()[HEADER_WORDS..]
+    }
+}
+
+impl Drop for Region {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len are exactly what mmap returned and nothing borrows the mapping
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// Writes dumps into a shared region for [`ShmReader`]s
+pub struct ShmPublisher {
+    region: Region,
+    file: File,
+    path: PathBuf,
+}
+
+impl ShmPublisher {
+    /// Creates the region at `path`, usually a file in `/dev/shm`, accessible only by our user
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(path)?;
+        Self::from_file(file, path.to_path_buf())
+    }
+
+    /// Creates the region as an anonymous memfd.  Other processes of our user can open it
+    /// through [`ShmPublisher::path`] for as long as this process runs.
+    pub fn memfd() -> io::Result<Self> {
+        let name = b"celeste-dump\0";
+        // SAFETY: name is nul terminated.  libc does not wrap memfd_create in this version
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_memfd_create,
+                name.as_ptr() as *const libc::c_char,
+                libc::MFD_CLOEXEC,
+            )
+        } as libc::c_int;
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: fd was just created and nothing else owns it
+        let file = unsafe { File::from_raw_fd(fd) };
+        let path = PathBuf::from(format!("/proc/{}/fd/{}", process::id(), fd));
+        Self::from_file(file, path)
+    }
+
+    fn from_file(file: File, path: PathBuf) -> io::Result<Self> {
+        file.set_len(REGION_SIZE as u64)?;
+        let region = Region::map(&file, true)?;
+
+        // A previous writer may have died while writing
+        let seq = region.seq().load(Ordering::Relaxed);
+        if seq % 2 == 1 {
+            region.seq().store(seq + 1, Ordering::Release);
+        }
+
+        Ok(ShmPublisher { region, file, path })
+    }
+
+    /// The path readers can open the region at
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn publish(&self, dump: &Dump) -> io::Result<()> {
+        let data = dump.encode();
+        let words = self.region.data();
+        if data.len() > words.len() * 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "dump of {} bytes does not fit the shared region",
+                    data.len()
+                ),
+            ));
+        }
+
+        let seq = self.region.seq().load(Ordering::Relaxed);
+        self.region.seq().store(seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        self.region
+            .dump_len()
+            .store(data.len() as u64, Ordering::Relaxed);
+        for (word, chunk) in words.iter().zip(data.chunks(8)) {
+            let mut bytes = [0_u8; 8];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            word.store(u64::from_le_bytes(bytes), Ordering::Relaxed);
+        }
+
+        self.region.seq().store(seq + 2, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl fmt::Debug for ShmPublisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShmPublisher")
+            .field("file", &self.file)
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Reads the dumps of a [`ShmPublisher`], possibly in another process
+pub struct ShmReader {
+    region: Region,
+}
+
+impl ShmReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        Ok(ShmReader {
+            region: Region::map(&file, false)?,
+        })
+    }
+
+    /// Returns the latest dump, or None if nothing was published yet or the writer kept
+    /// changing it while it was being copied
+    pub fn latest(&self) -> Result<Option<Dump>, FormatError> {
+        let words = self.region.data();
+        let mut data = Vec::with_capacity(words.len() * 8);
+
+        for _ in 0..MAX_READ_ATTEMPTS {
+            let seq = self.region.seq().load(Ordering::Acquire);
+            if seq == 0 {
+                return Ok(None);
+            }
+            if seq % 2 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+
+            let len =
+                (self.region.dump_len().load(Ordering::Relaxed) as usize).min(words.len() * 8);
+            data.clear();
+            for word in &words[..len.div_ceil(8)] {
+                data.extend(word.load(Ordering::Relaxed).to_le_bytes());
+            }
+
+            fence(Ordering::Acquire);
+            if self.region.seq().load(Ordering::Relaxed) == seq {
+                return Dump::decode(&data[..len]).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The sequence number of the latest dump, which only changes when a new one is published
+    pub fn sequence(&self) -> u64 {
+        self.region.seq().load(Ordering::Acquire) & !1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, sync::Arc, thread};
+
+    /// A region file in the temp directory, removed again when dropped
+    struct TempRegion(PathBuf);
+
+    impl TempRegion {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("celeste-shm-{}-{}", process::id(), name));
+            TempRegion(path)
+        }
+    }
+
+    impl Drop for TempRegion {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn dump(generation: u64) -> Dump {
+        let mut dump = Dump {
+            generation,
+            death_count: generation as u32,
+            ..Dump::default()
+        };
+        dump.set_level_name(&format!("room-{}", generation));
+        dump
+    }
+
+    #[test]
+    fn reads_the_last_published_dump() {
+        let temp = TempRegion::new("last");
+        let publisher = ShmPublisher::create(&temp.0).unwrap();
+        let reader = ShmReader::open(&temp.0).unwrap();
+        assert!(reader.latest().unwrap().is_none());
+        assert_eq!(reader.sequence(), 0);
+
+        for generation in 1..=3 {
+            publisher.publish(&dump(generation)).unwrap();
+        }
+        let latest = reader.latest().unwrap().unwrap();
+        assert_eq!(latest.generation, 3);
+        assert_eq!(latest.level_name(), "room-3");
+        assert_eq!(reader.sequence(), 6);
+    }
+
+    #[test]
+    fn never_reads_a_dump_being_written() {
+        let temp = TempRegion::new("odd");
+        let publisher = ShmPublisher::create(&temp.0).unwrap();
+        publisher.publish(&dump(1)).unwrap();
+        let reader = ShmReader::open(&temp.0).unwrap();
+
+        // Stop the writer halfway through, after it marked the dump as being written
+        let seq = publisher.region.seq().load(Ordering::Relaxed);
+        publisher.region.seq().store(seq + 1, Ordering::Release);
+        assert!(reader.latest().unwrap().is_none());
+        assert_eq!(reader.sequence(), seq);
+
+        // The next writer finishes the abandoned write before publishing its own
+        drop(publisher);
+        let publisher = ShmPublisher::create(&temp.0).unwrap();
+        assert_eq!(reader.latest().unwrap().unwrap().generation, 1);
+        publisher.publish(&dump(2)).unwrap();
+        assert_eq!(reader.latest().unwrap().unwrap().generation, 2);
+    }
+
+    #[test]
+    fn reads_consistent_dumps_while_publishing() {
+        let temp = TempRegion::new("concurrent");
+        let publisher = Arc::new(ShmPublisher::create(&temp.0).unwrap());
+        let reader = ShmReader::open(&temp.0).unwrap();
+
+        let writer = {
+            let publisher = publisher.clone();
+            thread::spawn(move || {
+                for generation in 1..=20_000 {
+                    publisher.publish(&dump(generation)).unwrap();
+                }
+            })
+        };
+
+        let mut last = 0;
+        while !writer.is_finished() || last < 20_000 {
+            if let Some(read) = reader.latest().unwrap() {
+                assert_eq!(read.death_count as u64, read.generation);
+                assert_eq!(read.level_name(), format!("room-{}", read.generation));
+                assert!(read.generation >= last);
+                last = read.generation;
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn rejects_dumps_larger_than_the_region() {
+        let temp = TempRegion::new("oversized");
+        let publisher = ShmPublisher::create(&temp.0).unwrap();
+        let reader = ShmReader::open(&temp.0).unwrap();
+        publisher.publish(&dump(1)).unwrap();
+
+        let mut oversized = dump(2);
+        oversized.set_level_name(&"x".repeat(REGION_SIZE));
+        let err = publisher.publish(&oversized).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        assert_eq!(fs::metadata(&temp.0).unwrap().len(), REGION_SIZE as u64);
+        assert_eq!(reader.sequence(), 2);
+        assert_eq!(reader.latest().unwrap().unwrap().generation, 1);
+    }
+}
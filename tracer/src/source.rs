@@ -0,0 +1,42 @@
+use std::{fs::File, io, os::unix::fs::FileExt, path::PathBuf};
+
+/// Somewhere the memory of the game can be read from
+pub trait MemorySource: Send {
+    /// Fills `buf` with the memory starting at `addr`, failing if any of it is unreadable
+    fn read_at(&mut self, addr: usize, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Returns the current contents of `/proc/<pid>/maps` for the game, used to reject bad
+    /// pointers before reading them
+    fn maps(&mut self) -> io::Result<String> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this source does not provide mappings",
+        ))
+    }
+}
+
+/// Reads directly from `/proc/<pid>/mem`, which needs ptrace access to the game
+#[derive(Debug)]
+pub struct ProcMem {
+    mem_file: File,
+    maps_path: PathBuf,
+}
+
+impl ProcMem {
+    pub fn new(pid: u32, mem_file: File) -> Self {
+        Self {
+            mem_file,
+            maps_path: PathBuf::from(format!("/proc/{}/maps", pid)),
+        }
+    }
+}
+
+impl MemorySource for ProcMem {
+    fn read_at(&mut self, addr: usize, buf: &mut [u8]) -> io::Result<()> {
+        self.mem_file.read_exact_at(buf, addr as u64)
+    }
+
+    fn maps(&mut self) -> io::Result<String> {
+        std::fs::read_to_string(&self.maps_path)
+    }
+}
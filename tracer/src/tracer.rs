@@ -1,27 +1,200 @@
-use std::{
-    fs::File,
-    io::{self, Read, Seek, SeekFrom},
-    mem,
-    path::PathBuf,
-    process,
-    sync::{Arc, Mutex},
-};
+use std::{error::Error, fmt, fs::File, io, mem, path::PathBuf, slice, time::Duration};
 
-use once_cell::sync::OnceCell;
+use crate::{
+    diagnose_permissions, DecodeError, Mapping, MemoryMap, MemorySource, PermissionReport,
+};
 
-pub fn load_mem(pid: u32) -> File {
+/// Opens the memory of `pid`, working out why when access is denied
+pub fn load_mem(pid: u32) -> Result<File, TraceError> {
     let path = PathBuf::from(format!("/proc/{}/mem", pid));
-    File::open(path).unwrap_or_else(|e| {
-        if let io::ErrorKind::PermissionDenied = e.kind() {
-            eprintln!("Permission to access memory file for {} denied", pid);
-            process::exit(1);
-        } else {
-            panic!("Unable to open mem file for process {}: {}", pid, e);
-        }
+    File::open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::PermissionDenied => TraceError::Permission(diagnose_permissions(pid)),
+        _ => TraceError::Open(pid, e),
     })
 }
 
-pub static MEM_FILE: OnceCell<Arc<Mutex<Option<File>>>> = OnceCell::new();
+#[derive(Debug)]
+pub enum TraceError {
+    /// Opening the memory of the process failed, usually because it exited
+    Open(u32, io::Error),
+    /// Access to the memory of the process was denied, with everything that decides it
+    Permission(PermissionReport),
+    /// Reading the memory of the process failed
+    Read(usize, io::Error),
+    /// A structure that the game should have was not found, usually because it is still starting
+    Missing(String),
+    /// The traced process is not (or no longer) Celeste
+    NotCeleste(String),
+    /// A structure was read, but held values that are not valid
+    Decode(usize, DecodeError),
+    /// The game kept changing its state while it was read, even after this many attempts
+    Inconsistent(usize),
+    /// The address and length to read are not in a readable mapping, with the nearest mapping
+    Unmapped(usize, usize, Option<Mapping>),
+    /// The string at the address claims to be longer than any the game uses
+    StringTooLong(usize, usize),
+    /// Writing out what was read failed
+    Output(io::Error),
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Open(pid, e) => {
+                write!(f, "Unable to open the memory of process {}: {}", pid, e)
+            }
+            TraceError::Permission(report) => write!(f, "{}", report),
+            TraceError::Read(addr, e) => write!(f, "Unable to read memory at {:#X}: {}", addr, e),
+            TraceError::Missing(what) => write!(f, "Could not find {}", what),
+            TraceError::NotCeleste(reason) => write!(f, "This is not Celeste: {}", reason),
+            TraceError::Decode(addr, e) => write!(f, "Unable to decode data at {:#X}: {}", addr, e),
+            TraceError::Inconsistent(attempts) => write!(
+                f,
+                "Unable to read a consistent game state in {} attempts",
+                attempts
+            ),
+            TraceError::Unmapped(addr, len, nearest) => {
+                write!(
+                    f,
+                    "{:#X} ({} bytes) is not in a readable mapping",
+                    addr, len
+                )?;
+                match nearest {
+                    Some(nearest) => write!(f, ", nearest is {}", nearest),
+                    None => write!(f, ", the process has no mappings"),
+                }
+            }
+            TraceError::StringTooLong(addr, len) => write!(
+                f,
+                "The string at {:#X} claims a length of {}, which is too long",
+                addr, len
+            ),
+            TraceError::Output(e) => write!(f, "Unable to write output: {}", e),
+        }
+    }
+}
+
+impl Error for TraceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TraceError::Open(_, e) | TraceError::Read(_, e) => Some(e),
+            TraceError::Decode(_, e) => Some(e),
+            TraceError::Output(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Strings longer than this are rejected, as room and class names are never close to it
+const MAX_STRING_LEN: usize = 4096;
+/// Mappings older than this are reloaded before the next read
+const MAP_MAX_AGE: Duration = Duration::from_secs(2);
+/// Mappings are never reloaded more often than this, even while reads keep missing
+const MAP_MIN_AGE: Duration = Duration::from_millis(50);
+
+/// A memory source along with the mappings of the process, used to reject stale pointers
+/// without a failing read
+pub struct Memory {
+    source: Box<dyn MemorySource>,
+    // None if the source is unable to provide mappings, which disables the checks
+    map: Option<MemoryMap>,
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memory")
+            .field(
+                "mappings",
+                &self.map.as_ref().map(|map| map.mappings().len()),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Memory {
+    pub fn new(source: Box<dyn MemorySource>) -> Self {
+        let mut memory = Memory { source, map: None };
+        memory.refresh_map();
+        memory
+    }
+
+    pub fn read(&mut self, addr: usize, out: &mut [u8]) -> Result<(), TraceError> {
+        self.check_readable(addr, out.len())?;
+        self.source.read_at(addr, out).map_err(|e| {
+            // The memory may have been unmapped since the last refresh
+            if self.map_older_than(MAP_MIN_AGE) {
+                self.refresh_map();
+            }
+            if self.is_readable(addr, out.len()) {
+                TraceError::Read(addr, e)
+            } else {
+                self.unmapped(addr, out.len())
+            }
+        })
+    }
+
+    /// Fails like [`Memory::read`] when `len` bytes at `addr` are not in a readable mapping,
+    /// without reading them
+    pub fn check_readable(&mut self, addr: usize, len: usize) -> Result<(), TraceError> {
+        if self.map_older_than(MAP_MAX_AGE) {
+            self.refresh_map();
+        }
+
+        if !self.is_readable(addr, len) {
+            // The pointer may be into something mapped since the last refresh
+            if self.map_older_than(MAP_MIN_AGE) {
+                self.refresh_map();
+            }
+            if !self.is_readable(addr, len) {
+                return Err(self.unmapped(addr, len));
+            }
+        }
+        Ok(())
+    }
+
+    /// The mappings of the process, refreshed if they are old, or None if the source has none
+    pub fn map(&mut self) -> Option<&MemoryMap> {
+        if self.map.is_none() || self.map_older_than(MAP_MIN_AGE) {
+            self.refresh_map();
+        }
+        self.map.as_ref()
+    }
+
+    fn refresh_map(&mut self) {
+        self.map = self.source.maps().ok().map(|maps| MemoryMap::parse(&maps));
+    }
+
+    fn map_older_than(&self, age: Duration) -> bool {
+        self.map
+            .as_ref()
+            .is_some_and(|map| map.loaded_at().elapsed() >= age)
+    }
+
+    fn is_readable(&self, addr: usize, len: usize) -> bool {
+        self.map
+            .as_ref()
+            .is_none_or(|map| map.is_readable(addr, len))
+    }
+
+    fn unmapped(&self, addr: usize, len: usize) -> TraceError {
+        let nearest = self.map.as_ref().and_then(|map| map.nearest(addr)).cloned();
+        TraceError::Unmapped(addr, len, nearest)
+    }
+}
+
+/// Types that can be read directly out of game memory.
+///
+/// # Safety
+/// Every bit pattern must be a valid value of the type, so types like `bool`, `char`,
+/// enums and references must never implement this.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u16 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for i32 {}
+unsafe impl Pod for i64 {}
 
 pub struct MemPtr(usize);
 
@@ -30,161 +203,141 @@ impl MemPtr {
         Self(addr)
     }
 
-    // SAFETY: a T must be valid at the specified offset (basically ptr read)
-    pub unsafe fn read<T>(&self) -> T
-    where
-        T: Copy,
-        [(); mem::size_of::<T>()]: ,
-    {
-        let lock = &mut MEM_FILE
-            .get()
-            .expect("Mem file not initialized")
-            .lock()
-            .expect("Unable to lock mem file");
-        let mem_file: &mut File = lock.as_mut().expect("Mem file deinitialized");
-        mem_file
-            .seek(SeekFrom::Start(self.0 as u64))
-            .expect("Unable to read memory");
-        let mut buf = [0_u8; mem::size_of::<T>()];
-        mem_file
-            .read_exact(&mut buf)
-            .unwrap_or_else(|_| panic!("Unable to read memory at {:#X}", self.0));
-        unsafe { *(buf.as_ptr() as *const T) }
-    }
-
-    // SAFETY: a T must be valid at the specified offset (basically ptr read)
-    // the provided pointer must be valid for writes for the specified number of writes of size T
-    pub unsafe fn read_into<T>(&self, out: &mut [T])
-    where
-        T: Copy,
-    {
-        let lock = &mut MEM_FILE
-            .get()
-            .expect("Mem file not initialized")
-            .lock()
-            .expect("Unable to lock mem file");
-        let mem_file: &mut File = lock.as_mut().expect("Mem file not initialized");
-        mem_file
-            .seek(SeekFrom::Start(self.0 as u64))
-            .expect("Unable to read memory");
-
-        let count = out.len();
-        let mut out = unsafe {
-            std::slice::from_raw_parts_mut(out as *mut [T] as *mut u8, count * mem::size_of::<T>())
+    pub fn read<T: Pod>(&self, memory: &mut Memory) -> Result<T, TraceError> {
+        let mut value = mem::MaybeUninit::<T>::zeroed();
+        // SAFETY: the value is zeroed, so all of its bytes are initialized
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, mem::size_of::<T>())
         };
-        mem_file
-            .read_exact(&mut out)
-            .unwrap_or_else(|_| panic!("Unable to read memory at {:#X}", self.0));
+        memory.read(self.0, bytes)?;
+        // SAFETY: T is Pod, so whatever bytes were read are a valid T
+        Ok(unsafe { value.assume_init() })
     }
-}
 
-pub unsafe fn read_u64(addr: usize) -> u64 {
-    unsafe { MemPtr::new(addr).read::<u64>() }
+    pub fn read_into<T: Pod>(&self, memory: &mut Memory, out: &mut [T]) -> Result<(), TraceError> {
+        // SAFETY: T is Pod, so any bytes written through this view leave valid Ts behind
+        let bytes = unsafe {
+            slice::from_raw_parts_mut(out.as_mut_ptr() as *mut u8, mem::size_of_val(out))
+        };
+        memory.read(self.0, bytes)
+    }
 }
 
-pub unsafe fn read_u32(addr: usize) -> u32 {
-    unsafe { MemPtr::new(addr).read::<u32>() }
+pub fn read_u64(mem: &mut Memory, addr: usize) -> Result<u64, TraceError> {
+    MemPtr::new(addr).read::<u64>(mem)
 }
 
-pub unsafe fn read_u8(addr: usize) -> u8 {
-    unsafe { MemPtr::new(addr).read::<u8>() }
+pub fn read_u32(mem: &mut Memory, addr: usize) -> Result<u32, TraceError> {
+    MemPtr::new(addr).read::<u32>(mem)
 }
 
-pub unsafe fn read_string(addr: usize) -> String {
-    unsafe {
-        let mut buf = vec![0_u8; 100];
-        MemPtr::new(addr).read_into(&mut buf);
-        buf.set_len(100);
-        let data = buf.into_iter().take_while(|&c| c != 0).collect::<Vec<_>>();
-        String::from_utf8_unchecked(data)
-    }
+pub fn read_u8(mem: &mut Memory, addr: usize) -> Result<u8, TraceError> {
+    MemPtr::new(addr).read::<u8>(mem)
 }
 
-pub fn read_boxed_string(instance: usize) -> String {
-    unsafe {
-        let class = instance_class(instance);
-        let data_offset = class_field_offset(class, "m_firstChar");
-        let size_offset = class_field_offset(class, "m_stringLength");
-        let size = read_u32(instance + size_offset) as usize;
+pub fn read_string(mem: &mut Memory, addr: usize) -> Result<String, TraceError> {
+    let mut buf = vec![0_u8; 100];
+    MemPtr::new(addr).read_into(mem, &mut buf)?;
+    let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+    Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
 
-        let mut utf16 = vec![0_u16; size];
-        MemPtr::new(instance + data_offset).read_into(&mut utf16);
-        utf16.set_len(size);
-        String::from_utf16_lossy(&utf16)
+pub fn read_boxed_string(mem: &mut Memory, instance: usize) -> Result<String, TraceError> {
+    let class = instance_class(mem, instance)?;
+    let data_offset = class_field_offset(mem, class, "m_firstChar")?;
+    let size_offset = class_field_offset(mem, class, "m_stringLength")?;
+    let size = read_u32(mem, instance + size_offset)? as usize;
+    // A stale pointer can read as any length, so check it before allocating for it
+    if size > MAX_STRING_LEN {
+        return Err(TraceError::StringTooLong(instance, size));
     }
+    mem.check_readable(instance + data_offset, size * 2)?;
+
+    let mut utf16 = vec![0_u16; size];
+    MemPtr::new(instance + data_offset).read_into(mem, &mut utf16)?;
+    Ok(String::from_utf16_lossy(&utf16))
 }
 
-pub unsafe fn class_name(class: usize) -> String {
-    unsafe {
-        let name_ptr = read_u64(class + 0x40) as usize;
-        read_string(name_ptr as usize)
-    }
+pub fn class_name(mem: &mut Memory, class: usize) -> Result<String, TraceError> {
+    let name_ptr = read_u64(mem, class + 0x40)? as usize;
+    read_string(mem, name_ptr)
 }
 
-pub unsafe fn lookup_class<S: AsRef<str>>(class_cache: usize, name: S) -> usize {
+pub fn lookup_class<S: AsRef<str>>(
+    mem: &mut Memory,
+    class_cache: usize,
+    name: S,
+) -> Result<usize, TraceError> {
     let target_name = name.as_ref();
-    unsafe {
-        let cache_table = read_u64(class_cache + 0x20) as usize;
-        let hash_table_size = read_u32(cache_table + 0x18) as usize;
-
-        for bucket in 0..hash_table_size {
-            let mut class = read_u64(cache_table + 8 * bucket) as usize;
-            while class != 0 {
-                let class_name = class_name(class);
-                if class_name == target_name {
-                    return class as usize;
-                }
-
-                class = read_u64(class + 0xF8) as usize;
+    let cache_table = read_u64(mem, class_cache + 0x20)? as usize;
+    let hash_table_size = read_u32(mem, cache_table + 0x18)? as usize;
+
+    for bucket in 0..hash_table_size {
+        let mut class = read_u64(mem, cache_table + 8 * bucket)? as usize;
+        while class != 0 {
+            let class_name = class_name(mem, class)?;
+            if class_name == target_name {
+                return Ok(class);
             }
-        }
 
-        panic!("Could not find class {}", target_name);
+            class = read_u64(mem, class + 0xF8)? as usize;
+        }
     }
+
+    Err(TraceError::Missing(format!("class {}", target_name)))
 }
 
-pub unsafe fn instance_class(instance: usize) -> usize {
-    unsafe { read_u64(read_u64(instance) as usize & (!1_i32 as usize)) as usize }
+pub fn instance_class(mem: &mut Memory, instance: usize) -> Result<usize, TraceError> {
+    let vtable = read_u64(mem, instance)? as usize & (!1_i32 as usize);
+    Ok(read_u64(mem, vtable)? as usize)
 }
 
-pub unsafe fn class_static_fields(class: usize) -> u64 {
-    unsafe {
-        let vtable_size = read_u32(class + 0x54);
-        let runtime_info = read_u64(class + 0xC8);
-        let max_domains = read_u64(runtime_info as usize) as usize;
+pub fn class_static_fields(mem: &mut Memory, class: usize) -> Result<u64, TraceError> {
+    let vtable_size = read_u32(mem, class + 0x54)?;
+    let runtime_info = read_u64(mem, class + 0xC8)?;
+    let max_domains = read_u64(mem, runtime_info as usize)? as usize;
 
-        for i in 0..=max_domains {
-            let vtable = read_u64(runtime_info as usize + 8 + 8 * i);
-            if vtable != 0 {
-                return read_u64(vtable as usize + 64 + 8 * vtable_size as usize);
-            }
+    for i in 0..=max_domains {
+        let vtable = read_u64(mem, runtime_info as usize + 8 + 8 * i)?;
+        if vtable != 0 {
+            return read_u64(mem, vtable as usize + 64 + 8 * vtable_size as usize);
         }
-
-        panic!("No domain has class {:#X} loaded", class);
     }
+
+    Err(TraceError::Missing(format!(
+        "a domain with class {:#X} loaded",
+        class
+    )))
 }
 
 #[derive(Clone, Copy, Debug)]
-#[repr(u8)]
-#[allow(dead_code)] // the variants are transmuted from u8
 enum MonoTypeKind {
-    MonoClassDef = 1,
-    MonoClassGTD = 2,
-    MonoClassGInst = 3,
-    MonoClassGParam = 4,
-    MonoClassArray = 5,
-    MonoClassPointer = 6,
+    MonoClassDef,
+    MonoClassGTD,
+    MonoClassGInst,
+    MonoClassGParam,
+    MonoClassArray,
+    MonoClassPointer,
 }
 
 impl MonoTypeKind {
-    fn from_u8(v: u8) -> Self {
-        assert!((1..=6).contains(&v), "Value out of range: {}", v);
-        unsafe { mem::transmute(v) }
+    fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(MonoTypeKind::MonoClassDef),
+            2 => Some(MonoTypeKind::MonoClassGTD),
+            3 => Some(MonoTypeKind::MonoClassGInst),
+            4 => Some(MonoTypeKind::MonoClassGParam),
+            5 => Some(MonoTypeKind::MonoClassArray),
+            6 => Some(MonoTypeKind::MonoClassPointer),
+            _ => None,
+        }
     }
 }
 
-fn class_kind(class: usize) -> MonoTypeKind {
-    unsafe { MonoTypeKind::from_u8(read_u8(class + 0x24) & 7) }
+fn class_kind(mem: &mut Memory, class: usize) -> Result<MonoTypeKind, TraceError> {
+    let kind = read_u8(mem, class + 0x24)? & 7;
+    MonoTypeKind::from_u8(kind)
+        .ok_or_else(|| TraceError::Missing(format!("a valid kind for class {:#X}", class)))
 }
 
 #[derive(Clone, Copy, Debug, Default)]
@@ -196,60 +349,70 @@ struct MonoClassField {
     offset: u32,
 }
 
-pub unsafe fn class_field_offset(class: usize, name: &str) -> usize {
-    let kind = class_kind(class);
-    unsafe {
-        match kind {
-            MonoTypeKind::MonoClassGInst => {
-                class_field_offset(read_u64(read_u64(class + 0xE0) as usize) as usize, name)
-            }
-            MonoTypeKind::MonoClassDef | MonoTypeKind::MonoClassGTD => {
-                let num_fields = read_u32(class + 0xF0);
-                let fields_ptr = read_u64(class + 0x90);
-
-                for i in 0..num_fields as usize {
-                    let field: MonoClassField =
-                        MemPtr::new(fields_ptr as usize + i * mem::size_of::<MonoClassField>())
-                            .read();
-                    let nametest = read_string(field.name as usize);
-                    if name == nametest {
-                        return field.offset as usize;
-                    }
-                }
+// SAFETY: all fields are integers, which are valid for any bit pattern
+unsafe impl Pod for MonoClassField {}
 
-                panic!("Failed to find name {}", name);
-            }
-            _ => {
-                panic!("Something is wrong");
+pub fn class_field_offset(mem: &mut Memory, class: usize, name: &str) -> Result<usize, TraceError> {
+    let kind = class_kind(mem, class)?;
+    match kind {
+        MonoTypeKind::MonoClassGInst => {
+            let generic_class = read_u64(mem, class + 0xE0)? as usize;
+            let container_class = read_u64(mem, generic_class)? as usize;
+            class_field_offset(mem, container_class, name)
+        }
+        MonoTypeKind::MonoClassDef | MonoTypeKind::MonoClassGTD => {
+            let num_fields = read_u32(mem, class + 0xF0)?;
+            let fields_ptr = read_u64(mem, class + 0x90)?;
+
+            for i in 0..num_fields as usize {
+                let field: MonoClassField =
+                    MemPtr::new(fields_ptr as usize + i * mem::size_of::<MonoClassField>())
+                        .read(mem)?;
+                let nametest = read_string(mem, field.name as usize)?;
+                if name == nametest {
+                    return Ok(field.offset as usize);
+                }
             }
+
+            Err(TraceError::Missing(format!("field {}", name)))
         }
+        _ => Err(TraceError::Missing(format!(
+            "fields of class {:#X} with kind {:?}",
+            class, kind
+        ))),
     }
 }
 
-pub unsafe fn static_field_u64<S: AsRef<str>>(class: usize, name: S) -> u64 {
-    unsafe {
-        let static_data = class_static_fields(class);
-        let field_offset = class_field_offset(class, name.as_ref());
-        read_u64(static_data as usize + field_offset)
-    }
+pub fn static_field_u64<S: AsRef<str>>(
+    mem: &mut Memory,
+    class: usize,
+    name: S,
+) -> Result<u64, TraceError> {
+    let static_data = class_static_fields(mem, class)?;
+    let field_offset = class_field_offset(mem, class, name.as_ref())?;
+    read_u64(mem, static_data as usize + field_offset)
 }
 
-pub unsafe fn instance_field_u32<S: AsRef<str>>(instance: usize, name: S) -> u32 {
-    unsafe {
-        let class = instance_class(instance);
-        let field_offset = class_field_offset(class, name.as_ref());
-        read_u32(instance + field_offset)
-    }
+pub fn instance_field_u32<S: AsRef<str>>(
+    mem: &mut Memory,
+    instance: usize,
+    name: S,
+) -> Result<u32, TraceError> {
+    let class = instance_class(mem, instance)?;
+    let field_offset = class_field_offset(mem, class, name.as_ref())?;
+    read_u32(mem, instance + field_offset)
 }
 
-pub unsafe fn instance_field_u64<S: AsRef<str>>(instance: usize, name: S) -> u64 {
-    unsafe {
-        let class = instance_class(instance);
-        let field_offset = class_field_offset(class, name.as_ref());
-        read_u64(instance + field_offset)
-    }
+pub fn instance_field_u64<S: AsRef<str>>(
+    mem: &mut Memory,
+    instance: usize,
+    name: S,
+) -> Result<u64, TraceError> {
+    let class = instance_class(mem, instance)?;
+    let field_offset = class_field_offset(mem, class, name.as_ref())?;
+    read_u64(mem, instance + field_offset)
 }
 
-pub fn locate_autosplitter_info(instance: usize) -> usize {
-    unsafe { instance_field_u64(instance, "AutoSplitterInfo") as usize + 0x10 }
+pub fn locate_autosplitter_info(mem: &mut Memory, instance: usize) -> Result<usize, TraceError> {
+    Ok(instance_field_u64(mem, instance, "AutoSplitterInfo")? as usize + 0x10)
 }